@@ -0,0 +1,406 @@
+use crate::error::{Error, Result};
+use crate::rational::{Rational, evaluate_exact};
+use crate::{Environment, Expr, evaluate};
+use std::fmt::Display;
+
+/// Built-in functions and the `(min_args, max_args)` each accepts. Every
+/// call is checked against this table before dispatch, so adding a function
+/// means adding an entry here and an arm in `call`. `repeat` and `reduce`
+/// are the exceptions: `repeat` needs its first argument unevaluated, so
+/// `evaluate` sends it to `repeat` below instead, and `reduce` gives two
+/// values, so only `evaluate_all` can run it. `evaluate_exact` goes through
+/// `call_exact` and `repeat_exact`.
+pub const BUILTINS: &[(&str, usize, usize)] = &[
+    ("abs", 1, 1),
+    ("max", 2, 2),
+    ("min", 2, 2),
+    ("clamp", 3, 3),
+    ("print", 1, 1),
+    ("sign", 1, 1),
+    ("ans", 1, 1),
+    ("repeat", 2, 2),
+    ("log", 1, 2),
+    ("sum", 0, usize::MAX),
+    ("reduce", 2, 2),
+    ("nth_root", 2, 2),
+    ("rand", 2, 2),
+    ("bit_count", 1, 1),
+    ("leading_zeros", 1, 1),
+    ("trailing_zeros", 1, 1),
+];
+
+/// The most times `repeat` will evaluate its body, to stop runaway loops.
+pub const MAX_REPEAT: i32 = 10_000;
+
+pub fn arity(name: &str) -> Option<(usize, usize)> {
+    BUILTINS
+        .iter()
+        .find(|(builtin, _, _)| *builtin == name)
+        .map(|&(_, min, max)| (min, max))
+}
+
+/// Human-readable form of an arity, e.g. `1` or `2 to 3`.
+pub fn describe_arity(min: usize, max: usize) -> String {
+    if min == max {
+        min.to_string()
+    } else if max == usize::MAX {
+        format!("at least {min}")
+    } else {
+        format!("{min} to {max}")
+    }
+}
+
+pub(crate) fn check_arity(name: &str, got: usize) -> Result<()> {
+    let (min, max) = arity(name).ok_or_else(|| Error::UnknownFunction(name.to_string()))?;
+    if got < min || got > max {
+        return Err(Error::WrongArity {
+            name: name.to_string(),
+            expected: describe_arity(min, max),
+            got,
+        });
+    }
+    Ok(())
+}
+
+/// Evaluates `count`, then evaluates `body` that many times and sums the
+/// results, so side effects such as `print` happen once per round.
+pub(crate) fn repeat(body: &Expr, count: &Expr, env: &mut Environment) -> Result<i32> {
+    let n = evaluate(count, env)?;
+    check_repeat_count(n)?;
+    let mut sum: i64 = 0;
+    for _ in 0..n {
+        sum += i64::from(evaluate(body, env)?);
+    }
+    i32::try_from(sum).map_err(|_| Error::Overflow(format!("repeat total {sum}")))
+}
+
+/// `repeat` for `evaluate_exact`: the count must be whole, and the body's
+/// values are summed exactly.
+pub(crate) fn repeat_exact(body: &Expr, count: &Expr, env: &mut Environment) -> Result<Rational> {
+    let n = evaluate_exact(count, env)?.whole("repeat")?;
+    check_repeat_count(n)?;
+    let mut sum = Rational::from(0);
+    for _ in 0..n {
+        sum = sum.checked_add(evaluate_exact(body, env)?)?;
+    }
+    Ok(sum)
+}
+
+fn check_repeat_count(n: i32) -> Result<()> {
+    if !(0..=MAX_REPEAT).contains(&n) {
+        return Err(Error::MathError(format!(
+            "repeat count {n} must be between 0 and {MAX_REPEAT}"
+        )));
+    }
+    Ok(())
+}
+
+/// Adds in i64, which can't overflow for any number of i32 arguments a
+/// call can hold, so only the final total has to fit: `sum(2147483647, 1,
+/// -1) == 2147483647`.
+fn sum(args: &[i32]) -> Result<i32> {
+    let total: i64 = args.iter().copied().map(i64::from).sum();
+    i32::try_from(total).map_err(|_| Error::Overflow(format!("sum total {total}")))
+}
+
+/// The fraction `n/d` in lowest terms as `[num, den]`, with `den`
+/// positive: `reduce(6, -8) == [-3, 4]`.
+pub(crate) fn reduce(n: i32, d: i32) -> Result<[i32; 2]> {
+    let r = Rational::new(n, d)?;
+    Ok([r.num(), r.den()])
+}
+
+/// The integer `n`th root, rounded toward zero: `nth_root(27, 3) == 3`
+/// and `nth_root(-30, 3) == -3`. Found by binary search, so no float rounding can
+/// put it one off.
+fn nth_root(x: i32, n: i32) -> Result<i32> {
+    if n < 1 {
+        return Err(Error::MathError(format!(
+            "root degree {n} must be at least 1"
+        )));
+    }
+    if x < 0 && n % 2 == 0 {
+        return Err(Error::MathError(format!(
+            "even root of negative number {x}"
+        )));
+    }
+    let target = u64::from(x.unsigned_abs());
+    let (mut lo, mut hi) = (0, target);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        if mid.checked_pow(n as u32).is_some_and(|p| p <= target) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    let root = if x < 0 { -(lo as i64) } else { lo as i64 };
+    i32::try_from(root).map_err(|_| Error::Overflow(format!("nth_root({x}, {n})")))
+}
+
+/// A pseudo-random integer in `lo..=hi`, from the environment's seeded
+/// sequence.
+fn rand(lo: i32, hi: i32, env: &mut Environment) -> Result<i32> {
+    if lo > hi {
+        return Err(Error::MathError(format!(
+            "rand lower bound {lo} is above upper bound {hi}"
+        )));
+    }
+    let span = (i64::from(hi) - i64::from(lo) + 1) as u64;
+    Ok((i64::from(lo) + (env.next_random() % span) as i64) as i32)
+}
+
+/// The integer logarithm, rounded down: `log(1000) == 3` and
+/// `log(8, 2) == 3`. With only i32 values there's no `ln` or `exp`.
+fn log(x: i32, base: i32) -> Result<i32> {
+    if x <= 0 {
+        return Err(Error::MathError(format!("log of non-positive number {x}")));
+    }
+    if base < 2 {
+        return Err(Error::MathError(format!(
+            "log base {base} must be at least 2"
+        )));
+    }
+    Ok(x.ilog(base) as i32)
+}
+
+/// Dispatches a built-in whose arguments have already passed `check_arity`.
+/// `env` is used by `ans`, which reads it, `rand`, which advances its
+/// random sequence, and `print`, which it may capture.
+pub(crate) fn call(name: &str, args: &[i32], env: &mut Environment) -> Result<i32> {
+    let out = match name {
+        "ans" => env.answer(args[0])?,
+        "abs" => args[0]
+            .checked_abs()
+            .ok_or_else(|| Error::Overflow(format!("abs({})", args[0])))?,
+        "max" => args[0].max(args[1]),
+        "sign" => args[0].signum(),
+        "log" => log(args[0], args.get(1).copied().unwrap_or(10))?,
+        "min" => args[0].min(args[1]),
+        "sum" => sum(args)?,
+        "nth_root" => nth_root(args[0], args[1])?,
+        "rand" => rand(args[0], args[1], env)?,
+        // Bit counts see the 32-bit two's complement pattern, so
+        // bit_count(-1) == 32 and leading_zeros of any negative number is 0.
+        // Zero has no set bit, so leading_zeros(0) == trailing_zeros(0) == 32.
+        "bit_count" => args[0].count_ones() as i32,
+        "leading_zeros" => args[0].leading_zeros() as i32,
+        "trailing_zeros" => args[0].trailing_zeros() as i32,
+        "reduce" => {
+            return Err(Error::MathError(
+                "reduce gives two values, so it must be a whole statement or list item".to_string(),
+            ));
+        }
+        "print" => {
+            env.print(args[0]);
+            args[0]
+        }
+        "clamp" => clamp(args[0], args[1], args[2])?,
+        _ => return Err(Error::UnknownFunction(name.to_string())),
+    };
+    Ok(out)
+}
+
+/// `call` for `evaluate_exact`. The built-ins that only order or add their
+/// arguments take fractions; the rest need whole numbers and go to `call`.
+pub(crate) fn call_exact(name: &str, args: &[Rational], env: &mut Environment) -> Result<Rational> {
+    let out = match name {
+        "abs" if args[0] < Rational::from(0) => args[0].checked_neg()?,
+        "abs" => args[0],
+        "sign" => Rational::from(args[0].num().signum()),
+        "max" => args[0].max(args[1]),
+        "min" => args[0].min(args[1]),
+        "clamp" => clamp(args[0], args[1], args[2])?,
+        "sum" => args
+            .iter()
+            .try_fold(Rational::from(0), |total, &arg| total.checked_add(arg))?,
+        _ => {
+            let args = args
+                .iter()
+                .map(|arg| arg.whole(name))
+                .collect::<Result<Vec<i32>>>()?;
+            Rational::from(call(name, &args, env)?)
+        }
+    };
+    Ok(out)
+}
+
+fn clamp<T: Ord + Display>(x: T, lo: T, hi: T) -> Result<T> {
+    if lo > hi {
+        return Err(Error::MathError(format!(
+            "clamp lower bound {lo} is above upper bound {hi}"
+        )));
+    }
+    Ok(x.clamp(lo, hi))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval_str;
+
+    fn eval(source: &str) -> Result<i32> {
+        eval_str(source, &mut Environment::new())
+    }
+
+    fn wrong_arity(source: &str) -> (String, String, usize) {
+        match eval(source) {
+            Err(Error::WrongArity {
+                name,
+                expected,
+                got,
+            }) => (name, expected, got),
+            other => panic!("{source} gave {other:?}"),
+        }
+    }
+
+    #[test]
+    fn too_few_and_too_many_arguments() {
+        assert_eq!(
+            wrong_arity("max(1)"),
+            ("max".to_string(), "2".to_string(), 1)
+        );
+        assert_eq!(
+            wrong_arity("max(1, 2, 3)"),
+            ("max".to_string(), "2".to_string(), 3)
+        );
+        assert_eq!(
+            wrong_arity("abs()"),
+            ("abs".to_string(), "1".to_string(), 0)
+        );
+        assert_eq!(
+            wrong_arity("abs(1, 2)"),
+            ("abs".to_string(), "1".to_string(), 2)
+        );
+        assert_eq!(eval("max(1, 2)").unwrap(), 2);
+    }
+
+    #[test]
+    fn unknown_functions_are_reported_before_arity() {
+        assert!(matches!(eval("nope(1)"), Err(Error::UnknownFunction(name)) if name == "nope"));
+    }
+
+    #[test]
+    fn clamp_branches() {
+        assert_eq!(eval("clamp(-5, 0, 10)").unwrap(), 0);
+        assert_eq!(eval("clamp(15, 0, 10)").unwrap(), 10);
+        assert_eq!(eval("clamp(7, 0, 10)").unwrap(), 7);
+        assert_eq!(eval("clamp(3, 3, 3)").unwrap(), 3);
+    }
+
+    #[test]
+    fn clamp_rejects_inverted_bounds() {
+        assert!(matches!(eval("clamp(5, 10, 0)"), Err(Error::MathError(_))));
+    }
+
+    #[test]
+    fn abs_of_i32_min_overflows() {
+        assert!(matches!(eval("abs(-2147483648)"), Err(Error::Overflow(_))));
+        assert_eq!(eval("abs(-5)").unwrap(), 5);
+        assert_eq!(eval("abs(2147483647)").unwrap(), i32::MAX);
+    }
+
+    #[test]
+    fn sign_of_negative_zero_and_positive() {
+        assert_eq!(eval("sign(-3)").unwrap(), -1);
+        assert_eq!(eval("sign(0)").unwrap(), 0);
+        assert_eq!(eval("sign(7)").unwrap(), 1);
+        assert_eq!(eval("sign(-2147483648)").unwrap(), -1);
+    }
+
+    #[test]
+    fn repeat_sums_its_body_count_times() {
+        assert_eq!(eval("repeat(2, 5)").unwrap(), 10);
+        assert_eq!(eval("repeat(2, 0)").unwrap(), 0);
+        // The body runs afresh each round, side effects and all.
+        let mut env = Environment::new();
+        env.assign("x", 0).unwrap();
+        assert_eq!(eval_str("repeat(x = x + 1, 3)", &mut env).unwrap(), 6);
+        assert_eq!(env.lookup("x").unwrap(), 3);
+        assert!(matches!(eval("repeat(1, -1)"), Err(Error::MathError(_))));
+        assert!(matches!(
+            eval(&format!("repeat(1, {})", MAX_REPEAT + 1)),
+            Err(Error::MathError(_))
+        ));
+    }
+
+    #[test]
+    fn integer_log_and_its_domain() {
+        assert_eq!(eval("log(100)").unwrap(), 2);
+        assert_eq!(eval("log(999)").unwrap(), 2);
+        assert_eq!(eval("log(8, 2)").unwrap(), 3);
+        assert_eq!(eval("log(1)").unwrap(), 0);
+        assert!(matches!(eval("log(0)"), Err(Error::MathError(_))));
+        assert!(matches!(eval("log(-5)"), Err(Error::MathError(_))));
+        assert!(matches!(eval("log(8, 1)"), Err(Error::MathError(_))));
+    }
+
+    #[test]
+    fn sum_only_needs_the_total_to_fit() {
+        assert_eq!(eval("sum(2147483647, 1, -1)").unwrap(), i32::MAX);
+        assert_eq!(eval("sum(-2147483648, -1, 1)").unwrap(), i32::MIN);
+        assert_eq!(eval("sum()").unwrap(), 0);
+        assert!(matches!(
+            eval("sum(2147483647, 1)"),
+            Err(Error::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn reduce_normalises_fractions() {
+        assert_eq!(reduce(6, 8).unwrap(), [3, 4]);
+        assert_eq!(reduce(3, 4).unwrap(), [3, 4]);
+        assert_eq!(reduce(6, -8).unwrap(), [-3, 4]);
+        assert_eq!(reduce(0, 5).unwrap(), [0, 1]);
+        assert!(matches!(reduce(1, 0), Err(Error::DivisionByZero)));
+        // It gives two values, so it can't sit inside a larger expression.
+        assert!(matches!(eval("1 + reduce(6, 8)"), Err(Error::MathError(_))));
+    }
+
+    #[test]
+    fn nth_root_and_its_domain() {
+        assert_eq!(eval("nth_root(27, 3)").unwrap(), 3);
+        assert_eq!(eval("nth_root(30, 3)").unwrap(), 3);
+        assert_eq!(eval("nth_root(-30, 3)").unwrap(), -3);
+        assert_eq!(eval("nth_root(2147483647, 1)").unwrap(), i32::MAX);
+        assert!(matches!(eval("nth_root(-4, 2)"), Err(Error::MathError(_))));
+        assert!(matches!(eval("nth_root(8, 0)"), Err(Error::MathError(_))));
+        assert!(matches!(
+            eval("root(27, 3)"),
+            Err(Error::UnknownFunction(_))
+        ));
+    }
+
+    #[test]
+    fn a_fixed_seed_gives_a_reproducible_sequence() {
+        let draws = |seed: u64| {
+            let mut env = Environment::new();
+            env.seed(seed);
+            (0..20)
+                .map(|_| eval_str("rand(1, 6)", &mut env).unwrap())
+                .collect::<Vec<i32>>()
+        };
+        assert_eq!(draws(42), draws(42));
+        assert_ne!(draws(42), draws(43));
+        assert!(draws(0).iter().all(|val| (1..=6).contains(val)));
+
+        let mut env = Environment::new();
+        env.seed(7);
+        assert!(eval_str("rand(-2147483648, 2147483647)", &mut env).is_ok());
+        assert_eq!(eval_str("rand(3, 3)", &mut env).unwrap(), 3);
+        assert!(matches!(eval("rand(2, 1)"), Err(Error::MathError(_))));
+    }
+
+    #[test]
+    fn bit_counts_of_known_values() {
+        assert_eq!(eval("bit_count(7)").unwrap(), 3);
+        assert_eq!(eval("bit_count(-1)").unwrap(), 32);
+        assert_eq!(eval("bit_count(0)").unwrap(), 0);
+        assert_eq!(eval("leading_zeros(1)").unwrap(), 31);
+        assert_eq!(eval("leading_zeros(-1)").unwrap(), 0);
+        assert_eq!(eval("leading_zeros(0)").unwrap(), 32);
+        assert_eq!(eval("trailing_zeros(8)").unwrap(), 3);
+        assert_eq!(eval("trailing_zeros(-2147483648)").unwrap(), 31);
+        assert_eq!(eval("trailing_zeros(0)").unwrap(), 32);
+    }
+}
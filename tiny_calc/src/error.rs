@@ -3,12 +3,57 @@ pub type Result<T> = core::result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     SyntaxError(String),
+    UndefinedVariable(String),
+    UndefinedVariables(Vec<String>),
+    UnknownFunction(String),
+    MathError(String),
+    DivisionByZero,
+    Overflow(String),
+    InputTooLong {
+        len: usize,
+        max: usize,
+    },
+    /// A value outside the environment's configured bounds was assigned.
+    OutOfRange {
+        val: i32,
+        min: i32,
+        max: i32,
+    },
+    WrongArity {
+        name: String,
+        expected: String,
+        got: usize,
+    },
 }
 
 // region:    --- Error Boilerpate
 impl std::fmt::Display for Error {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> core::result::Result<(), std::fmt::Error> {
-        write!(fmt, "{self:?}")
+        match self {
+            Error::SyntaxError(msg) => write!(fmt, "SyntaxError: {msg}"),
+            Error::UndefinedVariable(name) => write!(fmt, "UndefinedVariable: {name}"),
+            Error::UndefinedVariables(names) => {
+                write!(fmt, "UndefinedVariables: {}", names.join(", "))
+            }
+            Error::UnknownFunction(name) => write!(fmt, "UnknownFunction: {name}"),
+            Error::MathError(msg) => write!(fmt, "MathError: {msg}"),
+            Error::DivisionByZero => write!(fmt, "DivisionByZero"),
+            Error::Overflow(what) => write!(fmt, "Overflow: {what} doesn't fit in i32"),
+            Error::InputTooLong { len, max } => {
+                write!(fmt, "InputTooLong: {len} bytes, the limit is {max}")
+            }
+            Error::OutOfRange { val, min, max } => {
+                write!(fmt, "OutOfRange: {val} is outside [{min}, {max}]")
+            }
+            Error::WrongArity {
+                name,
+                expected,
+                got,
+            } => write!(
+                fmt,
+                "WrongArity: {name} takes {expected} argument(s), got {got}"
+            ),
+        }
     }
 }
 
@@ -0,0 +1,3295 @@
+pub mod builtins;
+pub mod error;
+pub mod macros;
+pub mod rational;
+pub mod rpn;
+pub mod sexpr;
+
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::num::{IntErrorKind, ParseIntError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum TokenType {
+    Num,
+    Name,
+    Plus,
+    Minus,
+    Times,
+    Lparen,
+    Rparen,
+    /// `[` and `]` group like parentheses, but each must close its own kind.
+    Lbracket,
+    Rbracket,
+    Assign,
+    Not,
+    Semicolon,
+    /// A `#`, `//` or `/* */` comment, only produced by `tokenize_with_comments`.
+    Comment,
+    Comma,
+    Bang,
+    Slash,
+    Percent,
+    /// Exponentiation, spelled `^` or `**`.
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    EqEq,
+    NotEq,
+    /// Synthetic end-of-input marker that `tokenize` always appends.
+    Eof,
+}
+
+/// Char offsets `[start, end)` of a token within the tokenized source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub token_type: TokenType,
+    /// Source text. Operators and punctuation borrow a static string rather
+    /// than allocating one per token.
+    pub val: Cow<'static, str>,
+    pub span: Span,
+}
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Token {
+    fn eof(at: usize) -> Self {
+        Self {
+            token_type: TokenType::Eof,
+            val: Cow::Borrowed(""),
+            span: Span::new(at, at),
+        }
+    }
+    pub fn is_operator(&self) -> bool {
+        PRECEDENCE.iter().any(|(t, _, _)| *t == self.token_type)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fixity {
+    Prefix,
+    Infix(Assoc),
+    Postfix,
+}
+
+/// Every operator with its position and binding power (higher binds
+/// tighter). Prefix operators bind tighter than the arithmetic operators
+/// but looser than `^` and postfix `!`, so `-2 * 3` is `(-2) * 3`, `-2 ^ 2`
+/// is `-(2 ^ 2)` and `-3!` is `-(3!)`. A new operator needs a row here
+/// and a case in the matching `*_expr` builder.
+pub const PRECEDENCE: &[(TokenType, Fixity, u8)] = &[
+    (TokenType::Assign, Fixity::Infix(Assoc::Right), PREC_ASSIGN),
+    (TokenType::Lt, Fixity::Infix(Assoc::Left), PREC_COMPARE),
+    (TokenType::Le, Fixity::Infix(Assoc::Left), PREC_COMPARE),
+    (TokenType::Gt, Fixity::Infix(Assoc::Left), PREC_COMPARE),
+    (TokenType::Ge, Fixity::Infix(Assoc::Left), PREC_COMPARE),
+    (TokenType::EqEq, Fixity::Infix(Assoc::Left), PREC_COMPARE),
+    (TokenType::NotEq, Fixity::Infix(Assoc::Left), PREC_COMPARE),
+    (TokenType::Plus, Fixity::Infix(Assoc::Left), PREC_SUM),
+    (TokenType::Minus, Fixity::Infix(Assoc::Left), PREC_SUM),
+    (TokenType::Times, Fixity::Infix(Assoc::Left), PREC_PRODUCT),
+    (TokenType::Slash, Fixity::Infix(Assoc::Left), PREC_PRODUCT),
+    (TokenType::Percent, Fixity::Infix(Assoc::Left), PREC_PRODUCT),
+    (TokenType::Plus, Fixity::Prefix, PREC_PREFIX),
+    (TokenType::Minus, Fixity::Prefix, PREC_PREFIX),
+    (TokenType::Not, Fixity::Prefix, PREC_PREFIX),
+    (TokenType::Pow, Fixity::Infix(Assoc::Right), PREC_POW),
+    (TokenType::Bang, Fixity::Postfix, PREC_POSTFIX),
+];
+
+const PREC_ASSIGN: u8 = 1;
+const PREC_COMPARE: u8 = 2;
+const PREC_SUM: u8 = 3;
+const PREC_PRODUCT: u8 = 4;
+const PREC_PREFIX: u8 = 5;
+const PREC_POW: u8 = 6;
+const PREC_POSTFIX: u8 = 7;
+const PREC_ATOM: u8 = 8;
+
+/// Single-digit literals, the most common kind, borrow their text from
+/// here instead of allocating a `String` each.
+const DIGITS: &str = "0123456789";
+
+/// Operators spelled with two characters. The tokenizer tries these before
+/// single-character symbols, so `<=` is never read as `<` then `=`.
+const TWO_CHAR_SYMBOLS: &[(&str, TokenType)] = &[
+    ("**", TokenType::Pow),
+    ("<=", TokenType::Le),
+    (">=", TokenType::Ge),
+    ("==", TokenType::EqEq),
+    ("!=", TokenType::NotEq),
+];
+
+pub fn binary_precedence(token_type: TokenType) -> Option<(u8, Assoc)> {
+    PRECEDENCE
+        .iter()
+        .find_map(|&(t, fixity, prec)| match fixity {
+            Fixity::Infix(assoc) if t == token_type => Some((prec, assoc)),
+            _ => None,
+        })
+}
+
+pub fn prefix_precedence(token_type: TokenType) -> Option<u8> {
+    PRECEDENCE
+        .iter()
+        .find(|&&(t, fixity, _)| t == token_type && fixity == Fixity::Prefix)
+        .map(|&(_, _, prec)| prec)
+}
+
+pub fn postfix_precedence(token_type: TokenType) -> Option<u8> {
+    PRECEDENCE
+        .iter()
+        .find(|&&(t, fixity, _)| t == token_type && fixity == Fixity::Postfix)
+        .map(|&(_, _, prec)| prec)
+}
+
+/// How many tokens of each type `tokens` holds.
+pub fn token_type_counts(tokens: &[Token]) -> HashMap<TokenType, usize> {
+    let mut counts = HashMap::new();
+    for token in tokens {
+        *counts.entry(token.token_type).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Compact rendering of a token stream, e.g. `[Num(2), Plus, Name(x)]`.
+/// Only tokens whose text isn't implied by their type show a value, and
+/// the closing `Eof` is left out.
+pub fn format_tokens(tokens: &[Token]) -> String {
+    let parts: Vec<String> = tokens
+        .iter()
+        .filter(|t| t.token_type != TokenType::Eof)
+        .map(|t| match t.token_type {
+            TokenType::Num | TokenType::Name => format!("{:?}({})", t.token_type, t.val),
+            _ => format!("{:?}", t.token_type),
+        })
+        .collect();
+    format!("[{}]", parts.join(", "))
+}
+
+/// One token as `tokens_to_json` writes it.
+#[derive(Serialize)]
+struct JsonToken<'a> {
+    #[serde(rename = "type")]
+    token_type: TokenType,
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
+/// Token stream as a JSON array of `{"type", "text", "start", "end"}` objects,
+/// leaving out the closing `Eof`.
+pub fn tokens_to_json(tokens: &[Token]) -> String {
+    let items: Vec<JsonToken> = tokens
+        .iter()
+        .filter(|t| t.token_type != TokenType::Eof)
+        .map(|t| JsonToken {
+            token_type: t.token_type,
+            text: &t.val,
+            start: t.span.start,
+            end: t.span.end,
+        })
+        .collect();
+    serde_json::to_string(&items).expect("tokens always serialize")
+}
+
+/// One batch outcome as `result_to_json` writes it.
+#[derive(Serialize)]
+struct JsonOutcome<'a> {
+    input: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "<[i32]>::is_empty")]
+    printed: &'a [i32],
+}
+
+/// One outcome of a batch run as a JSON object:
+/// `{"input":"2+3","result":5}` on success or `{"input":"2+","error":"..."}`.
+/// Values the input printed, if any, follow as `"printed":[2]`.
+pub fn result_to_json(
+    input: &str,
+    result: std::result::Result<serde_json::Value, &Error>,
+    printed: &[i32],
+) -> String {
+    let (result, error) = match result {
+        Ok(val) => (Some(val), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let outcome = JsonOutcome {
+        input,
+        result,
+        error,
+        printed,
+    };
+    serde_json::to_string(&outcome).expect("outcomes always serialize")
+}
+
+/// Splits `source` into tokens. Whitespace only separates tokens and is
+/// otherwise insignificant: `2+3`, `2 + 3` and `  2  +  3 ` produce the same
+/// token types. `**` must be written without a gap to mean power.
+///
+/// Positions, both in spans and in error messages, count chars rather than
+/// bytes, so a multibyte character such as `é` advances the column by one;
+/// error columns start at 1.
+///
+/// A `#` or `//` starts a comment running to the end of the line, and
+/// `/* ... */` encloses one that may span lines; both are skipped. An
+/// unclosed `/*` is an error.
+pub fn tokenize(source: &str) -> Result<Vec<Token>> {
+    scan(source, false, None)
+}
+
+/// Like `tokenize`, but keeps each comment as a `Comment` token holding its
+/// text, markers included, for tools such as formatters that must preserve
+/// them. `Parser` ignores comment tokens.
+pub fn tokenize_with_comments(source: &str) -> Result<Vec<Token>> {
+    scan(source, true, None)
+}
+
+/// Whether `text` is a valid name: a letter, then any letters, digits and
+/// underscores, as in `x2` or `nth_root`.
+pub fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.all(is_name_char)
+}
+
+/// Whether `c` can continue a name once a letter has started it.
+pub(crate) fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// With `errors`, lexical errors are collected there along with their
+/// position, and scanning carries on past them, instead of the first one
+/// being returned.
+fn scan(
+    source: &str,
+    keep_comments: bool,
+    mut errors: Option<&mut Vec<(usize, Error)>>,
+) -> Result<Vec<Token>> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut n: usize = 0;
+    let source_chars: Vec<char> = source.chars().collect();
+    while n < source_chars.len() {
+        if source_chars[n].is_whitespace() {
+            n += 1;
+            continue;
+        } else if source_chars[n] == '#' || source_chars[n..].starts_with(&['/', '/']) {
+            let start = n;
+            while n < source_chars.len() && source_chars[n] != '\n' {
+                n += 1;
+            }
+            if keep_comments {
+                tokens.push(Token {
+                    token_type: TokenType::Comment,
+                    val: Cow::Owned(source_chars[start..n].iter().collect()),
+                    span: Span::new(start, n),
+                });
+            }
+        } else if source_chars[n..].starts_with(&['/', '*']) {
+            let start = n;
+            n += 2;
+            while n < source_chars.len() && !source_chars[n..].starts_with(&['*', '/']) {
+                n += 1;
+            }
+            if n == source_chars.len() {
+                let e = Error::SyntaxError(format!(
+                    "unterminated /* comment starting at column {}",
+                    start + 1
+                ));
+                match errors.as_deref_mut() {
+                    Some(errors) => {
+                        errors.push((start, e));
+                        continue;
+                    }
+                    None => return Err(e),
+                }
+            }
+            n += 2;
+            if keep_comments {
+                tokens.push(Token {
+                    token_type: TokenType::Comment,
+                    val: Cow::Owned(source_chars[start..n].iter().collect()),
+                    span: Span::new(start, n),
+                });
+            }
+        } else if let Some(&(text, token_type)) = TWO_CHAR_SYMBOLS.iter().find(|(text, _)| {
+            source_chars
+                .get(n..n + 2)
+                .is_some_and(|pair| text.chars().eq(pair.iter().copied()))
+        }) {
+            tokens.push(Token {
+                token_type,
+                val: Cow::Borrowed(text),
+                span: Span::new(n, n + 2),
+            });
+            n += 2;
+        } else if source_chars[n].is_ascii_digit() {
+            let start = n;
+            while n < source_chars.len() && source_chars[n].is_ascii_digit() {
+                n += 1;
+            }
+            let val = match &source_chars[start..n] {
+                &[digit] => {
+                    let d = digit as usize - '0' as usize;
+                    Cow::Borrowed(&DIGITS[d..=d])
+                }
+                digits => Cow::Owned(digits.iter().collect()),
+            };
+            tokens.push(Token {
+                token_type: TokenType::Num,
+                val,
+                span: Span::new(start, n),
+            });
+        } else if source_chars[n].is_ascii_alphabetic() {
+            let start = n;
+            while n < source_chars.len() && is_name_char(source_chars[n]) {
+                n += 1;
+            }
+            let val: String = source_chars[start..n].iter().collect();
+            let token_type = match val.as_str() {
+                "not" => TokenType::Not,
+                _ => TokenType::Name,
+            };
+            tokens.push(Token {
+                token_type,
+                val: Cow::Owned(val),
+                span: Span::new(start, n),
+            });
+        } else {
+            let symbol = match source_chars[n] {
+                '+' => Ok((TokenType::Plus, "+")),
+                '*' => Ok((TokenType::Times, "*")),
+                '-' => Ok((TokenType::Minus, "-")),
+                '(' => Ok((TokenType::Lparen, "(")),
+                ')' => Ok((TokenType::Rparen, ")")),
+                '[' => Ok((TokenType::Lbracket, "[")),
+                ']' => Ok((TokenType::Rbracket, "]")),
+                '=' => Ok((TokenType::Assign, "=")),
+                ';' => Ok((TokenType::Semicolon, ";")),
+                ',' => Ok((TokenType::Comma, ",")),
+                '!' => Ok((TokenType::Bang, "!")),
+                '/' => Ok((TokenType::Slash, "/")),
+                '%' => Ok((TokenType::Percent, "%")),
+                '^' => Ok((TokenType::Pow, "^")),
+                '<' => Ok((TokenType::Lt, "<")),
+                '>' => Ok((TokenType::Gt, ">")),
+                c => Err(Error::SyntaxError(format!(
+                    "unexpected character '{c}' at column {}",
+                    n + 1
+                ))),
+            };
+            let (token_type, text) = match (symbol, errors.as_deref_mut()) {
+                (Ok(symbol), _) => symbol,
+                (Err(e), Some(errors)) => {
+                    errors.push((n, e));
+                    n += 1;
+                    continue;
+                }
+                (Err(e), None) => return Err(e),
+            };
+            tokens.push(Token {
+                token_type,
+                val: Cow::Borrowed(text),
+                span: Span::new(n, n + 1),
+            });
+            n += 1;
+        }
+    }
+
+    tokens.push(Token::eof(source_chars.len()));
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number {
+        n: i32,
+    },
+    Variable {
+        name: String,
+    },
+    Assign {
+        location: Box<Expr>,
+        value: Box<Expr>,
+    },
+    /// One or more chained comparisons, `first op1 a op2 b ...`. Like
+    /// Python, `a < b < c` means `a < b and b < c` with `b` evaluated once,
+    /// rather than C's `(a < b) < c`. Evaluates to 1 or 0.
+    Compare {
+        first: Box<Expr>,
+        rest: Vec<(CompareOp, Expr)>,
+    },
+
+    Add {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+
+    Minus {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+
+    Mul {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+
+    Div {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+
+    Mod {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+
+    Pow {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+
+    Neg {
+        operand: Box<Expr>,
+    },
+
+    Factorial {
+        operand: Box<Expr>,
+    },
+
+    /// Logical negation: zero becomes 1, anything else becomes 0.
+    Not {
+        operand: Box<Expr>,
+    },
+
+    /// Explicit parentheses, only produced when the parser keeps groups.
+    Group {
+        inner: Box<Expr>,
+    },
+
+    /// Two or more top-level expressions separated by commas, `a, b, c`.
+    /// `evaluate` runs them in order and gives the last value;
+    /// `evaluate_all` gives every one.
+    List {
+        items: Vec<Expr>,
+    },
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl CompareOp {
+    fn from_token(token_type: TokenType) -> Option<Self> {
+        match token_type {
+            TokenType::Lt => Some(CompareOp::Lt),
+            TokenType::Le => Some(CompareOp::Le),
+            TokenType::Gt => Some(CompareOp::Gt),
+            TokenType::Ge => Some(CompareOp::Ge),
+            TokenType::EqEq => Some(CompareOp::Eq),
+            TokenType::NotEq => Some(CompareOp::Ne),
+            _ => None,
+        }
+    }
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        [
+            CompareOp::Lt,
+            CompareOp::Le,
+            CompareOp::Gt,
+            CompareOp::Ge,
+            CompareOp::Eq,
+            CompareOp::Ne,
+        ]
+        .into_iter()
+        .find(|op| op.symbol() == symbol)
+    }
+    pub fn symbol(self) -> &'static str {
+        match self {
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+        }
+    }
+    pub fn holds<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            CompareOp::Lt => left < right,
+            CompareOp::Le => left <= right,
+            CompareOp::Gt => left > right,
+            CompareOp::Ge => left >= right,
+            CompareOp::Eq => left == right,
+            CompareOp::Ne => left != right,
+        }
+    }
+}
+
+impl Expr {
+    /// Direct sub-expressions, in evaluation order.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Number { .. } | Expr::Variable { .. } => vec![],
+            Expr::Assign { location, value } => vec![location, value],
+            Expr::Compare { first, rest } => std::iter::once(&**first)
+                .chain(rest.iter().map(|(_, operand)| operand))
+                .collect(),
+            Expr::Add { left, right }
+            | Expr::Minus { left, right }
+            | Expr::Mul { left, right }
+            | Expr::Div { left, right }
+            | Expr::Mod { left, right }
+            | Expr::Pow { left, right } => vec![left, right],
+            Expr::Call { args, .. } => args.iter().collect(),
+            Expr::Neg { operand } | Expr::Factorial { operand } | Expr::Not { operand } => {
+                vec![operand]
+            }
+            Expr::Group { inner } => vec![inner],
+            Expr::List { items } => items.iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::List { .. } => 0,
+        Expr::Assign { .. } => PREC_ASSIGN,
+        Expr::Compare { .. } => PREC_COMPARE,
+        Expr::Add { .. } | Expr::Minus { .. } => PREC_SUM,
+        Expr::Mul { .. } | Expr::Div { .. } | Expr::Mod { .. } => PREC_PRODUCT,
+        Expr::Neg { .. } | Expr::Not { .. } => PREC_PREFIX,
+        Expr::Pow { .. } => PREC_POW,
+        Expr::Factorial { .. } => PREC_POSTFIX,
+        // The parser folds a leading `-` into the literal, but it still
+        // binds like a prefix minus: `(-2) ^ 2` can't be written `-2 ^ 2`.
+        Expr::Number { n } if *n < 0 => PREC_PREFIX,
+        Expr::Number { .. } | Expr::Variable { .. } | Expr::Call { .. } | Expr::Group { .. } => {
+            PREC_ATOM
+        }
+    }
+}
+
+/// Renders an expression back to infix source, adding only the parentheses
+/// the tree needs. `Expr::Group` nodes are always printed with theirs.
+pub fn unparse(expr: &Expr) -> String {
+    match expr {
+        Expr::Number { n } => n.to_string(),
+        Expr::Variable { name } => name.clone(),
+        Expr::Assign { location, value } => {
+            format!("{} = {}", unparse(location), unparse(value))
+        }
+        Expr::Compare { first, rest } => {
+            let mut out = unparse_operand(first, PREC_COMPARE + 1);
+            for (op, operand) in rest {
+                out.push_str(&format!(
+                    " {} {}",
+                    op.symbol(),
+                    unparse_operand(operand, PREC_COMPARE + 1)
+                ));
+            }
+            out
+        }
+        Expr::Add { left, right } => unparse_binary(left, "+", right, PREC_SUM),
+        Expr::Minus { left, right } => unparse_binary(left, "-", right, PREC_SUM),
+        Expr::Mul { left, right } => unparse_binary(left, "*", right, PREC_PRODUCT),
+        Expr::Div { left, right } => unparse_binary(left, "/", right, PREC_PRODUCT),
+        Expr::Mod { left, right } => unparse_binary(left, "%", right, PREC_PRODUCT),
+        Expr::Pow { left, right } => format!(
+            "{} ^ {}",
+            unparse_operand(left, PREC_POW + 1),
+            unparse_operand(right, PREC_POW)
+        ),
+        Expr::Call { name, args } => {
+            let args: Vec<String> = args.iter().map(unparse).collect();
+            format!("{name}({})", args.join(", "))
+        }
+        Expr::Neg { operand } => format!("-{}", unparse_operand(operand, PREC_PREFIX + 1)),
+        Expr::Factorial { operand } => {
+            format!("{}!", unparse_operand(operand, PREC_POSTFIX))
+        }
+        Expr::Not { operand } => format!("not {}", unparse_operand(operand, PREC_PREFIX + 1)),
+        Expr::Group { inner } => format!("({})", unparse(inner)),
+        Expr::List { items } => {
+            let items: Vec<String> = items.iter().map(unparse).collect();
+            items.join(", ")
+        }
+    }
+}
+
+fn unparse_binary(left: &Expr, op: &str, right: &Expr, prec: u8) -> String {
+    format!(
+        "{} {op} {}",
+        unparse_operand(left, prec),
+        unparse_operand(right, prec + 1)
+    )
+}
+
+fn unparse_operand(expr: &Expr, min_prec: u8) -> String {
+    if precedence(expr) < min_prec {
+        format!("({})", unparse(expr))
+    } else {
+        unparse(expr)
+    }
+}
+
+/// Describes an expression in English, following the parsed tree rather
+/// than the source, so `2 + 3 * 4` reads "add 2 to (multiply 3 by 4)".
+/// Compound operands are parenthesised to show what groups with what.
+pub fn explain(expr: &Expr) -> String {
+    match expr {
+        Expr::Number { n } => n.to_string(),
+        Expr::Variable { name } => name.clone(),
+        Expr::Assign { location, value } => {
+            format!("set {} to {}", explain(location), explain_operand(value))
+        }
+        Expr::Compare { first, rest } => {
+            let mut left = &**first;
+            let links: Vec<String> = rest
+                .iter()
+                .map(|(op, right)| {
+                    let relation = match op {
+                        CompareOp::Lt => "is less than",
+                        CompareOp::Le => "is at most",
+                        CompareOp::Gt => "is greater than",
+                        CompareOp::Ge => "is at least",
+                        CompareOp::Eq => "equals",
+                        CompareOp::Ne => "does not equal",
+                    };
+                    let link = format!(
+                        "{} {relation} {}",
+                        explain_operand(left),
+                        explain_operand(right)
+                    );
+                    left = right;
+                    link
+                })
+                .collect();
+            format!("1 if {}, else 0", links.join(" and "))
+        }
+        Expr::Add { left, right } => {
+            format!(
+                "add {} to {}",
+                explain_operand(left),
+                explain_operand(right)
+            )
+        }
+        Expr::Minus { left, right } => format!(
+            "subtract {} from {}",
+            explain_operand(right),
+            explain_operand(left)
+        ),
+        Expr::Mul { left, right } => {
+            format!(
+                "multiply {} by {}",
+                explain_operand(left),
+                explain_operand(right)
+            )
+        }
+        Expr::Div { left, right } => {
+            format!(
+                "divide {} by {}",
+                explain_operand(left),
+                explain_operand(right)
+            )
+        }
+        Expr::Mod { left, right } => format!(
+            "the remainder of {} divided by {}",
+            explain_operand(left),
+            explain_operand(right)
+        ),
+        Expr::Pow { left, right } => format!(
+            "raise {} to the power of {}",
+            explain_operand(left),
+            explain_operand(right)
+        ),
+        Expr::Call { name, args } => {
+            let args: Vec<String> = args.iter().map(explain_operand).collect();
+            if args.is_empty() {
+                format!("call {name}")
+            } else {
+                format!("call {name} with {}", args.join(", "))
+            }
+        }
+        Expr::Neg { operand } => format!("negate {}", explain_operand(operand)),
+        Expr::Factorial { operand } => format!("the factorial of {}", explain_operand(operand)),
+        Expr::Not { operand } => format!("1 if {} is 0, else 0", explain_operand(operand)),
+        Expr::Group { inner } => explain(inner),
+        Expr::List { items } => {
+            let items: Vec<String> = items.iter().map(explain_operand).collect();
+            format!("the list of {}", items.join(", "))
+        }
+    }
+}
+
+fn explain_operand(expr: &Expr) -> String {
+    match expr {
+        Expr::Number { .. } | Expr::Variable { .. } => explain(expr),
+        Expr::Group { inner } => explain_operand(inner),
+        _ => format!("({})", explain(expr)),
+    }
+}
+
+/// Renders the tree as a Graphviz DOT digraph, one node per `Expr` with an
+/// edge to each child, e.g. for `dot -Tpng`.
+pub fn expr_to_dot(expr: &Expr) -> String {
+    let mut lines = vec!["digraph ast {".to_string()];
+    push_dot(expr, &mut 0, &mut lines);
+    lines.push("}".to_string());
+    lines.join("\n")
+}
+
+/// Writes `expr` and its subtree, returning the id given to `expr`.
+fn push_dot(expr: &Expr, next_id: &mut usize, lines: &mut Vec<String>) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let label = match expr {
+        Expr::Number { n } => n.to_string(),
+        Expr::Variable { name } | Expr::Call { name, .. } => name.clone(),
+        Expr::Assign { .. } => "=".to_string(),
+        Expr::Compare { rest, .. } => {
+            let ops: Vec<&str> = rest.iter().map(|(op, _)| op.symbol()).collect();
+            ops.join(" ")
+        }
+        Expr::Add { .. } => "+".to_string(),
+        Expr::Minus { .. } | Expr::Neg { .. } => "-".to_string(),
+        Expr::Mul { .. } => "*".to_string(),
+        Expr::Div { .. } => "/".to_string(),
+        Expr::Mod { .. } => "%".to_string(),
+        Expr::Pow { .. } => "^".to_string(),
+        Expr::Factorial { .. } => "!".to_string(),
+        Expr::Not { .. } => "not".to_string(),
+        Expr::Group { .. } => "( )".to_string(),
+        Expr::List { .. } => "list".to_string(),
+    };
+    lines.push(format!(
+        "    n{id} [label={}];",
+        serde_json::Value::from(label)
+    ));
+    for child in expr.children() {
+        let child_id = push_dot(child, next_id, lines);
+        lines.push(format!("    n{id} -> n{child_id};"));
+    }
+    id
+}
+
+/// A summary of an expression's make-up, from `expr_stats` or `Stats::of`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Stats {
+    /// Tokens in the source, not counting the closing `Eof`.
+    pub tokens: usize,
+    pub operators: usize,
+    /// Numbers and variables in the parsed tree.
+    pub operands: usize,
+    /// The deepest nesting of parentheses or brackets.
+    pub paren_depth: usize,
+    /// Nodes in the parsed tree.
+    pub nodes: usize,
+}
+
+impl Stats {
+    /// Counts what `expr` is made of, given the tokens it was parsed from.
+    pub fn of(tokens: &[Token], expr: &Expr) -> Self {
+        let mut stats = Stats::default();
+        let mut depth = 0;
+        for token in tokens {
+            match token.token_type {
+                TokenType::Eof => continue,
+                TokenType::Lparen | TokenType::Lbracket => {
+                    depth += 1;
+                    stats.paren_depth = stats.paren_depth.max(depth);
+                }
+                TokenType::Rparen | TokenType::Rbracket => depth = depth.saturating_sub(1),
+                _ if token.is_operator() => stats.operators += 1,
+                _ => {}
+            }
+            stats.tokens += 1;
+        }
+        count_nodes(expr, &mut stats);
+        stats
+    }
+}
+
+/// Tokenizes and parses `source` and counts what it's made of.
+pub fn expr_stats(source: &str) -> Result<Stats> {
+    let tokens = tokenize(source)?;
+    let expr = parse(&mut Parser::new(tokens.clone()))?;
+    Ok(Stats::of(&tokens, &expr))
+}
+
+fn count_nodes(expr: &Expr, stats: &mut Stats) {
+    stats.nodes += 1;
+    if matches!(expr, Expr::Number { .. } | Expr::Variable { .. }) {
+        stats.operands += 1;
+    }
+    for child in expr.children() {
+        count_nodes(child, stats);
+    }
+}
+
+/// The first pair of nodes, in pre-order, at which two trees differ, or
+/// `None` if they're equal. Nodes differ when they're different kinds,
+/// hold different numbers, names or comparison operators, or have a
+/// different number of children.
+pub fn first_difference<'a>(a: &'a Expr, b: &'a Expr) -> Option<(&'a Expr, &'a Expr)> {
+    let same_node = match (a, b) {
+        (Expr::Number { n: x }, Expr::Number { n: y }) => x == y,
+        (Expr::Variable { name: x }, Expr::Variable { name: y }) => x == y,
+        (Expr::Call { name: x, .. }, Expr::Call { name: y, .. }) => x == y,
+        (Expr::Compare { rest: x, .. }, Expr::Compare { rest: y, .. }) => {
+            x.iter().map(|(op, _)| op).eq(y.iter().map(|(op, _)| op))
+        }
+        _ => std::mem::discriminant(a) == std::mem::discriminant(b),
+    };
+    let (a_children, b_children) = (a.children(), b.children());
+    if !same_node || a_children.len() != b_children.len() {
+        return Some((a, b));
+    }
+    a_children
+        .into_iter()
+        .zip(b_children)
+        .find_map(|(a, b)| first_difference(a, b))
+}
+
+/// Returns `expr` with every read of the variable `name` replaced by a copy
+/// of `replacement`. Assignment targets are left alone, since `x = ...`
+/// can only assign to a variable.
+pub fn substitute(expr: Expr, name: &str, replacement: &Expr) -> Expr {
+    match expr {
+        Expr::Variable { name: ref var } if var == name => replacement.clone(),
+        Expr::Assign { location, value } => Expr::Assign {
+            location,
+            value: Box::new(substitute(*value, name, replacement)),
+        },
+        other => map_children(other, &mut |child| substitute(child, name, replacement)),
+    }
+}
+
+/// Statically checks that `expr` never reads a variable before it has a
+/// value, given the names already `defined`. Assignments inside `expr`
+/// count from the point they're evaluated, so `(x = 2) + x` passes.
+pub fn check(expr: &Expr, defined: &HashSet<String>) -> Result<()> {
+    let mut defined = defined.clone();
+    let mut undefined = Vec::new();
+    check_reads(expr, &mut defined, &mut undefined);
+    if undefined.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::UndefinedVariables(undefined))
+    }
+}
+
+fn check_reads(expr: &Expr, defined: &mut HashSet<String>, undefined: &mut Vec<String>) {
+    match expr {
+        Expr::Variable { name } => {
+            if !defined.contains(name) && !undefined.contains(name) {
+                undefined.push(name.clone());
+            }
+        }
+        Expr::Assign { location, value } => {
+            check_reads(value, defined, undefined);
+            if let Expr::Variable { name } = &**location {
+                defined.insert(name.clone());
+            }
+        }
+        _ => {
+            for child in expr.children() {
+                check_reads(child, defined, undefined);
+            }
+        }
+    }
+}
+
+/// Rebuilds `expr` with `f` applied to each of its direct children.
+fn map_children(expr: Expr, f: &mut impl FnMut(Expr) -> Expr) -> Expr {
+    let mut map = |e: Box<Expr>| Box::new(f(*e));
+    match expr {
+        Expr::Number { .. } | Expr::Variable { .. } => expr,
+        Expr::Assign { location, value } => Expr::Assign {
+            location: map(location),
+            value: map(value),
+        },
+        Expr::Compare { first, rest } => Expr::Compare {
+            first: map(first),
+            rest: rest
+                .into_iter()
+                .map(|(op, e)| (op, *map(Box::new(e))))
+                .collect(),
+        },
+        Expr::Add { left, right } => Expr::Add {
+            left: map(left),
+            right: map(right),
+        },
+        Expr::Minus { left, right } => Expr::Minus {
+            left: map(left),
+            right: map(right),
+        },
+        Expr::Mul { left, right } => Expr::Mul {
+            left: map(left),
+            right: map(right),
+        },
+        Expr::Div { left, right } => Expr::Div {
+            left: map(left),
+            right: map(right),
+        },
+        Expr::Mod { left, right } => Expr::Mod {
+            left: map(left),
+            right: map(right),
+        },
+        Expr::Pow { left, right } => Expr::Pow {
+            left: map(left),
+            right: map(right),
+        },
+        Expr::Call { name, args } => Expr::Call {
+            name,
+            args: args.into_iter().map(&mut *f).collect(),
+        },
+        Expr::Neg { operand } => Expr::Neg {
+            operand: map(operand),
+        },
+        Expr::Factorial { operand } => Expr::Factorial {
+            operand: map(operand),
+        },
+        Expr::Not { operand } => Expr::Not {
+            operand: map(operand),
+        },
+        Expr::Group { inner } => Expr::Group { inner: map(inner) },
+        Expr::List { items } => Expr::List {
+            items: items.into_iter().map(|e| *map(Box::new(e))).collect(),
+        },
+    }
+}
+
+#[derive(Debug)]
+pub struct Parser {
+    tokens: Vec<Token>,
+    n: usize,
+    keep_groups: bool,
+    /// How many calls deep the parser has recursed for the expression
+    /// being built.
+    depth: usize,
+    strict: bool,
+}
+
+/// Deepest the parser will recurse: each bracket, prefix operator or
+/// right-associative operand nested inside another is one level. A flat
+/// chain such as `1 + 2 + 3` is parsed in a loop and doesn't count.
+pub const MAX_NESTING: usize = 200;
+
+/// Tallest expression tree the parser will build. A flat chain doesn't
+/// make the parser recurse, but it does nest in the tree: `1 + 2 + 3` is
+/// `(1 + 2) + 3`. Every recursive pass over an `Expr`, evaluation
+/// included, uses stack in proportion to the tree's height, so capping it
+/// here means no input can overflow the stack.
+pub const MAX_HEIGHT: usize = 400;
+
+/// The height of a node whose tallest child is `height` levels tall, or an
+/// error if that would pass `MAX_HEIGHT`.
+pub(crate) fn taller(height: usize) -> Result<usize> {
+    if height >= MAX_HEIGHT {
+        return Err(Error::SyntaxError(format!(
+            "expression too long: its parse tree would be more than {MAX_HEIGHT} levels tall"
+        )));
+    }
+    Ok(height + 1)
+}
+
+impl Parser {
+    /// Creates a parser over `tokens`, dropping any comment tokens and
+    /// appending an `Eof` if the caller didn't get them from `tokenize`.
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        tokens.retain(|t| t.token_type != TokenType::Comment);
+        if tokens.last().map(|t| t.token_type) != Some(TokenType::Eof) {
+            let end = tokens.last().map_or(0, |t| t.span.end);
+            tokens.push(Token::eof(end));
+        }
+        Self {
+            tokens,
+            n: 0,
+            keep_groups: false,
+            depth: 0,
+            strict: false,
+        }
+    }
+    /// Preserve parentheses as `Expr::Group` nodes instead of dropping them,
+    /// so `unparse` can reproduce the user's grouping.
+    pub fn keep_groups(mut self, keep: bool) -> Self {
+        self.keep_groups = keep;
+        self
+    }
+    /// Refuse input that is only accepted by being lenient: in strict mode
+    /// `parse_program` rejects empty statements such as `1;;2` or a
+    /// trailing `;` instead of skipping them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+    /// Reads juxtaposition as multiplication, as in `2(3 + 4)`, `2x` and
+    /// `(1 + 1)(2 + 2)`: a `*` is inserted wherever a number or closing
+    /// bracket is directly followed by an opening bracket or a name. A name
+    /// followed by `(` is still a call. Off unless asked for.
+    pub fn implicit_mul(mut self, on: bool) -> Self {
+        if !on {
+            return self;
+        }
+        let mut tokens = Vec::with_capacity(self.tokens.len());
+        for token in self.tokens {
+            let implied = tokens.last().is_some_and(|prev: &Token| {
+                matches!(
+                    prev.token_type,
+                    TokenType::Num | TokenType::Rparen | TokenType::Rbracket
+                ) && matches!(
+                    token.token_type,
+                    TokenType::Lparen | TokenType::Lbracket | TokenType::Name
+                )
+            });
+            if implied {
+                tokens.push(Token {
+                    token_type: TokenType::Times,
+                    val: Cow::Borrowed("*"),
+                    span: Span::new(token.span.start, token.span.start),
+                });
+            }
+            tokens.push(token);
+        }
+        self.tokens = tokens;
+        self
+    }
+    /// Consumes the current token if it has `token_type`. `Eof` is never
+    /// consumed, so the parser can't run off the end of the stream.
+    fn accept(&mut self, token_type: TokenType) -> bool {
+        if token_type != TokenType::Eof && self.peek() == token_type {
+            self.n += 1;
+            return true;
+        }
+        false
+    }
+    fn peek(&self) -> TokenType {
+        self.tokens[self.n].token_type
+    }
+    fn last(&self) -> Result<Token> {
+        if self.n == 0 || self.n > self.tokens.len() {
+            return Err(Error::SyntaxError("Syntax error somewhere.".to_string()));
+        }
+        Ok(self.tokens[self.n - 1].clone())
+    }
+    fn at_end(&self) -> bool {
+        self.peek() == TokenType::Eof
+    }
+    fn deeper(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(Error::SyntaxError(format!(
+                "expression nested more than {MAX_NESTING} levels deep"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Parses a number, name, call or bracketed expression. A name is a call
+/// exactly when the next token is `(`; whitespace isn't a token, so
+/// `f (2)` is a call just like `f(2)`, and `f` alone is a variable.
+/// Returns the term with the height of its tree.
+fn parse_term(p: &mut Parser) -> Result<(Expr, usize)> {
+    if p.accept(TokenType::Num) {
+        let n = parse_number(&p.last()?.val)?;
+        Ok((Expr::Number { n }, 1))
+    } else if p.accept(TokenType::Name) {
+        let name = p.last()?.val.into_owned();
+        if p.accept(TokenType::Lparen) {
+            let (args, height) = parse_args(p)?;
+            Ok((Expr::Call { name, args }, taller(height)?))
+        } else {
+            Ok((Expr::Variable { name }, 1))
+        }
+    } else if p.accept(TokenType::Lparen) || p.accept(TokenType::Lbracket) {
+        let (close, open_text, close_text, kind) = match p.last()?.token_type {
+            TokenType::Lparen => (TokenType::Rparen, "(", ")", "parentheses"),
+            _ => (TokenType::Rbracket, "[", "]", "brackets"),
+        };
+        if p.peek() == close {
+            return Err(Error::SyntaxError(format!("empty {kind}")));
+        }
+        let (e, height) = parse_expression(p)?;
+        if matches!(p.peek(), TokenType::Rparen | TokenType::Rbracket) && p.peek() != close {
+            Err(Error::SyntaxError(format!(
+                "mismatched brackets: {open_text} closed by {}",
+                p.tokens[p.n].val
+            )))
+        } else if !p.accept(close) {
+            Err(Error::SyntaxError(format!(
+                "{open_text} not closed by a {close_text}. Found {open_text} {e} "
+            )))
+        } else if p.keep_groups {
+            Ok((Expr::Group { inner: Box::new(e) }, taller(height)?))
+        } else {
+            Ok((e, height))
+        }
+    } else {
+        match p.last() {
+            Ok(op) if op.is_operator() && p.tokens[p.n].is_operator() => {
+                Err(adjacent_operators(&op, &p.tokens[p.n]))
+            }
+            Ok(op) if op.is_operator() => Err(Error::SyntaxError(format!(
+                "expected expression after `{}`",
+                op.val
+            ))),
+            _ if p.tokens[p.n].is_operator() => Err(Error::SyntaxError(format!(
+                "expression cannot start with `{}`",
+                p.tokens[p.n].val
+            ))),
+            _ => Err(Error::SyntaxError("Cannot process token".to_string())),
+        }
+    }
+}
+
+fn adjacent_operators(first: &Token, second: &Token) -> Error {
+    Error::SyntaxError(format!(
+        "unexpected operator after operator: `{}` then `{}`",
+        first.val, second.val
+    ))
+}
+
+/// Parses a call's comma-separated arguments, after its opening `(`,
+/// with the height of the tallest.
+fn parse_args(p: &mut Parser) -> Result<(Vec<Expr>, usize)> {
+    let mut args = Vec::new();
+    let mut height = 0;
+    if p.accept(TokenType::Rparen) {
+        return Ok((args, height));
+    }
+    loop {
+        let (arg, arg_height) = parse_expression(p)?;
+        args.push(arg);
+        height = height.max(arg_height);
+        if p.accept(TokenType::Rparen) {
+            return Ok((args, height));
+        }
+        if !p.accept(TokenType::Comma) {
+            return Err(Error::SyntaxError(
+                "Expected , or ) in argument list".to_string(),
+            ));
+        }
+    }
+}
+
+fn parse_number(val: &str) -> Result<i32> {
+    val.parse().map_err(|e: ParseIntError| match e.kind() {
+        IntErrorKind::PosOverflow => Error::SyntaxError(format!(
+            "literal {val} out of range for i32: above the maximum {}",
+            i32::MAX
+        )),
+        IntErrorKind::NegOverflow => Error::SyntaxError(format!(
+            "literal {val} out of range for i32: below the minimum {}",
+            i32::MIN
+        )),
+        _ => Error::SyntaxError(format!("Couldn't parse {val} to a number")),
+    })
+}
+
+fn parse_expression(p: &mut Parser) -> Result<(Expr, usize)> {
+    parse_pratt(p, 1)
+}
+
+/// A top-level expression, or several separated by commas as an
+/// `Expr::List`. Commas inside a call's parentheses belong to the call.
+fn parse_list(p: &mut Parser) -> Result<Expr> {
+    let (first, mut height) = parse_expression(p)?;
+    if p.peek() != TokenType::Comma {
+        return Ok(first);
+    }
+    let mut items = vec![first];
+    while p.accept(TokenType::Comma) {
+        let (item, item_height) = parse_expression(p)?;
+        items.push(item);
+        height = height.max(item_height);
+    }
+    taller(height)?;
+    Ok(Expr::List { items })
+}
+
+/// Pratt parser: a prefix operator or term, followed by any postfix and
+/// binary operators that bind at least as tightly as `min_prec`. Returns
+/// the expression with the height of its tree.
+fn parse_pratt(p: &mut Parser, min_prec: u8) -> Result<(Expr, usize)> {
+    let depth = p.depth;
+    let out = parse_pratt_nested(p, min_prec);
+    p.depth = depth;
+    out
+}
+
+/// The body of `parse_pratt`. Each nested call counts as one more level
+/// towards `MAX_NESTING`; each node the loop wraps around `left` makes the
+/// tree one level taller, towards `MAX_HEIGHT`.
+fn parse_pratt_nested(p: &mut Parser, min_prec: u8) -> Result<(Expr, usize)> {
+    p.deeper()?;
+    let (mut left, mut height) = match prefix_precedence(p.peek()) {
+        Some(prec) => {
+            // A unary plus straight after another operator is almost always
+            // a typo such as `2 ++ 3`, so it's refused there.
+            if p.peek() == TokenType::Plus
+                && let Some(before) = p.last().ok().filter(|t| t.is_operator())
+            {
+                return Err(adjacent_operators(&before, &p.tokens[p.n]));
+            }
+            p.n += 1;
+            let op = p.last()?.token_type;
+            if op == TokenType::Minus && negative_literal_follows(p, prec) {
+                p.n += 1;
+                let n = parse_number(&format!("-{}", p.last()?.val))?;
+                (Expr::Number { n }, 1)
+            } else {
+                let (operand, height) = parse_pratt(p, prec)?;
+                match op {
+                    // Unary plus changes nothing, so it leaves no node behind.
+                    TokenType::Plus => (operand, height),
+                    _ => (prefix_expr(op, operand), taller(height)?),
+                }
+            }
+        }
+        None => parse_term(p)?,
+    };
+    // Set once this loop has built a comparison, so a following comparison
+    // extends the chain. A parenthesised comparison on the left starts a
+    // fresh one instead: `(a < b) < c` compares the 0/1 result with `c`.
+    let mut chaining = false;
+    loop {
+        let op = p.peek();
+        if let Some(prec) = postfix_precedence(op) {
+            if prec < min_prec {
+                break;
+            }
+            p.n += 1;
+            height = taller(height)?;
+            left = postfix_expr(op, left);
+        } else if let Some((prec, assoc)) = binary_precedence(op) {
+            if prec < min_prec {
+                break;
+            }
+            p.n += 1;
+            let next_min = match assoc {
+                Assoc::Left => prec + 1,
+                Assoc::Right => prec,
+            };
+            let (right, right_height) = parse_pratt(p, next_min)?;
+            left = match (left, CompareOp::from_token(op)) {
+                (Expr::Compare { first, mut rest }, Some(cmp)) if chaining => {
+                    // Another link in a chain sits beside the others, so
+                    // only a taller operand makes the tree taller.
+                    height = height.max(taller(right_height)?);
+                    rest.push((cmp, right));
+                    Expr::Compare { first, rest }
+                }
+                (left, cmp) => {
+                    chaining = cmp.is_some();
+                    height = taller(height.max(right_height))?;
+                    binary_expr(op, left, right)
+                }
+            };
+        } else {
+            break;
+        }
+    }
+    Ok((left, height))
+}
+
+/// After a prefix `-`, whether the next number can be read as a negative
+/// literal: true unless an operator binding tighter than the minus follows
+/// it, as in `-2 ^ 2`. Folding the sign in lets `-2147483648` reach
+/// `i32::MIN` without overflowing first.
+fn negative_literal_follows(p: &Parser, prefix_prec: u8) -> bool {
+    if p.peek() != TokenType::Num {
+        return false;
+    }
+    let after = p.tokens[p.n + 1].token_type;
+    let tighter = postfix_precedence(after)
+        .or_else(|| binary_precedence(after).map(|(prec, _)| prec))
+        .is_some_and(|prec| prec > prefix_prec);
+    !tighter
+}
+
+fn prefix_expr(op: TokenType, operand: Expr) -> Expr {
+    let operand = Box::new(operand);
+    match op {
+        TokenType::Minus => Expr::Neg { operand },
+        TokenType::Not => Expr::Not { operand },
+        _ => unreachable!("{op:?} is not a prefix operator"),
+    }
+}
+
+fn postfix_expr(op: TokenType, operand: Expr) -> Expr {
+    let operand = Box::new(operand);
+    match op {
+        TokenType::Bang => Expr::Factorial { operand },
+        _ => unreachable!("{op:?} is not a postfix operator"),
+    }
+}
+
+fn binary_expr(op: TokenType, left: Expr, right: Expr) -> Expr {
+    let (left, right) = (Box::new(left), Box::new(right));
+    match op {
+        TokenType::Assign => Expr::Assign {
+            location: left,
+            value: right,
+        },
+        TokenType::Plus => Expr::Add { left, right },
+        TokenType::Minus => Expr::Minus { left, right },
+        TokenType::Times => Expr::Mul { left, right },
+        TokenType::Slash => Expr::Div { left, right },
+        TokenType::Percent => Expr::Mod { left, right },
+        TokenType::Pow => Expr::Pow { left, right },
+        _ => match CompareOp::from_token(op) {
+            Some(op) => Expr::Compare {
+                first: left,
+                rest: vec![(op, *right)],
+            },
+            None => unreachable!("{op:?} is not a binary operator"),
+        },
+    }
+}
+
+pub fn parse(p: &mut Parser) -> Result<Expr> {
+    let e = parse_list(p)?;
+    if !p.at_end() {
+        return Err(Error::SyntaxError(
+            format!(
+                "Unprocessed characters remain. First unprocessed: {}",
+                p.tokens[p.n].val
+            )
+            .to_string(),
+        ));
+    }
+    Ok(e)
+}
+
+/// Parses a sequence of `;`-separated statements. Empty statements, such as
+/// a trailing `;`, are skipped unless the parser is strict.
+pub fn parse_program(p: &mut Parser) -> Result<Vec<Expr>> {
+    let mut statements = Vec::new();
+    while !p.at_end() {
+        if p.accept(TokenType::Semicolon) {
+            if p.strict {
+                return Err(empty_statement(p));
+            }
+            continue;
+        }
+        statements.push(parse_list(p)?);
+        if !p.at_end() && !p.accept(TokenType::Semicolon) {
+            return Err(Error::SyntaxError(format!(
+                "Expected ; between statements, found {}",
+                p.tokens[p.n].val
+            )));
+        }
+        if p.strict && p.at_end() && p.last()?.token_type == TokenType::Semicolon {
+            return Err(empty_statement(p));
+        }
+    }
+    Ok(statements)
+}
+
+/// Checks every `;`-separated statement of `input` without evaluating,
+/// for an editor's "check" action. Unlike `parse_program` it doesn't stop
+/// at the first problem: an unexpected character is skipped, a statement
+/// that fails to parse is abandoned at the next `;`, and every error is
+/// returned in source order. A statement with a lexical error isn't also
+/// parsed, so one mistake isn't reported twice. Parse errors are tagged
+/// with the column their statement starts at.
+pub fn validate(input: &str) -> Vec<Error> {
+    let mut lexical = Vec::new();
+    let tokens = scan(input, false, Some(&mut lexical))
+        .expect("scanning with an error list always succeeds");
+    let mut errors = Vec::new();
+    let mut statement: Vec<Token> = Vec::new();
+    let mut region_start = 0;
+    for token in tokens {
+        if !matches!(token.token_type, TokenType::Semicolon | TokenType::Eof) {
+            statement.push(token);
+            continue;
+        }
+        let region = region_start..token.span.end;
+        region_start = token.span.end;
+        let has_lexical = lexical.iter().any(|(at, _)| region.contains(at));
+        let Some(start) = statement.first().map(|t| t.span.start) else {
+            continue;
+        };
+        statement.push(Token::eof(token.span.start));
+        match parse(&mut Parser::new(std::mem::take(&mut statement))) {
+            Err(Error::SyntaxError(msg)) if !has_lexical => errors.push((
+                start,
+                Error::SyntaxError(format!("{msg} (in the statement at column {})", start + 1)),
+            )),
+            Err(e) if !has_lexical => errors.push((start, e)),
+            _ => {}
+        }
+    }
+    errors.append(&mut lexical);
+    errors.sort_by_key(|&(at, _)| at);
+    errors.into_iter().map(|(_, e)| e).collect()
+}
+
+/// The error for an empty statement in strict mode, pointing at the `;`
+/// just read.
+fn empty_statement(p: &Parser) -> Error {
+    let column = p.tokens[p.n - 1].span.start + 1;
+    Error::SyntaxError(format!("empty statement at `;` in column {column}"))
+}
+
+/// How `/` and `%` round when the operands have different signs.
+///
+/// `Truncated` rounds the quotient toward zero like Rust and C, so the
+/// remainder takes the dividend's sign: `-7 / 3 == -2` and `-7 % 3 == -1`.
+/// `Floored` rounds toward negative infinity like Python, so the remainder
+/// takes the divisor's sign: `-7 / 3 == -3` and `-7 % 3 == 2`. Both keep
+/// `a == (a / b) * b + a % b`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DivisionMode {
+    #[default]
+    Truncated,
+    Floored,
+}
+
+/// Which value `Environment::merge` keeps when both environments bind a
+/// name.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MergePolicy {
+    /// The incoming value replaces the existing one.
+    #[default]
+    Overwrite,
+    /// The existing value stays and the incoming one is dropped.
+    Keep,
+}
+
+/// Observer for `Environment::on_assign`.
+pub type AssignHook = Box<dyn FnMut(&str, i32)>;
+
+/// Variables, held in a stack of scopes. The first scope is global and is
+/// never popped; reads search from the innermost scope outwards.
+pub struct Environment {
+    scopes: Vec<HashMap<String, i32>>,
+    aliases: HashMap<String, Expr>,
+    /// For variables last set by an `=` expression, that expression's
+    /// right-hand side as source.
+    sources: HashMap<String, String>,
+    /// One-shot values from `assume`, read before any scope.
+    assumed: HashMap<String, i32>,
+    /// Aliases currently being evaluated, to catch self-reference.
+    expanding: Vec<String>,
+    pub division: DivisionMode,
+    /// Called with the name and new value each time `assign` stores one,
+    /// so an embedding UI can follow variable changes.
+    pub on_assign: Option<AssignHook>,
+    /// Inclusive bounds every stored value must fall within. They default
+    /// to the whole i32 range.
+    pub min_value: i32,
+    pub max_value: i32,
+    /// Spreadsheet-style reads: unbound variables are 0 instead of an error.
+    pub zero_default: bool,
+    /// Sub-expression values collected while `evaluate_traced` runs.
+    trace: Option<Vec<(String, i32)>>,
+    /// Operands short-circuiting left unevaluated while `evaluate_counted`
+    /// runs.
+    skipped: Option<usize>,
+    /// The most recent results, newest first, read back with `ans(n)`.
+    answers: VecDeque<i32>,
+    /// xorshift64* state behind `rand`; never 0.
+    rng: u64,
+    /// Values `print` was given while capturing, instead of writing them
+    /// to stdout.
+    printed: Option<Vec<i32>>,
+}
+
+/// How many past results `Environment` keeps for `ans`.
+pub const MAX_ANSWERS: usize = 100;
+
+impl Environment {
+    pub fn new() -> Self {
+        let mut env = Self {
+            scopes: vec![HashMap::new()],
+            aliases: HashMap::new(),
+            sources: HashMap::new(),
+            assumed: HashMap::new(),
+            expanding: Vec::new(),
+            division: DivisionMode::default(),
+            on_assign: None,
+            min_value: i32::MIN,
+            max_value: i32::MAX,
+            zero_default: false,
+            trace: None,
+            skipped: None,
+            answers: VecDeque::new(),
+            rng: 0,
+            printed: None,
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH);
+        env.seed(now.map_or(0, |d| d.as_nanos() as u64));
+        env
+    }
+    /// Updates `name` in the innermost scope that already holds it, or
+    /// creates it in the innermost scope if no scope does. Values outside
+    /// `min_value..=max_value` are refused with `Error::OutOfRange`.
+    pub fn assign(&mut self, name: &str, val: i32) -> Result<()> {
+        self.check_range(val)?;
+        self.assumed.remove(name);
+        match self.scopes.iter_mut().rev().find(|s| s.contains_key(name)) {
+            Some(scope) => scope.insert(name.to_string(), val),
+            None => self.top_scope().insert(name.to_string(), val),
+        };
+        self.sources.remove(name);
+        if let Some(on_assign) = &mut self.on_assign {
+            on_assign(name, val);
+        }
+        Ok(())
+    }
+    /// Creates `name` in the innermost scope, shadowing any outer binding.
+    pub fn declare(&mut self, name: &str, val: i32) -> Result<()> {
+        self.check_range(val)?;
+        self.top_scope().insert(name.to_string(), val);
+        self.sources.remove(name);
+        Ok(())
+    }
+    /// The innermost value of `name`. Unbound names are an
+    /// `Error::UndefinedVariable`, or read as 0 when `zero_default` is set.
+    pub fn lookup(&self, name: &str) -> Result<i32> {
+        let assumed = self.assumed.get(name);
+        match assumed.or_else(|| self.scopes.iter().rev().find_map(|s| s.get(name))) {
+            Some(&val) => Ok(val),
+            None if self.zero_default => Ok(0),
+            None => Err(Error::UndefinedVariable(name.to_string())),
+        }
+    }
+    /// Like `lookup`, but falls back to `default` when `name` isn't bound.
+    pub fn lookup_or(&self, name: &str, default: i32) -> i32 {
+        self.lookup(name).unwrap_or(default)
+    }
+    /// Makes `name` available to expressions evaluated against this environment.
+    pub fn define(&mut self, name: &str, val: i32) -> Result<()> {
+        self.assign(name, val)
+    }
+    /// Removes the innermost binding of `name`, uncovering any outer one.
+    pub fn undefine(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.iter_mut().rev().find(|s| s.contains_key(name)) {
+            scope.remove(name);
+        }
+        self.sources.remove(name);
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.assumed.contains_key(name) || self.scopes.iter().any(|s| s.contains_key(name))
+    }
+    /// Overrides `name` with `val` for reads until `drop_assumptions`,
+    /// leaving its real binding untouched underneath. Assigning `name`
+    /// ends the override early.
+    pub fn assume(&mut self, name: &str, val: i32) -> Result<()> {
+        self.check_range(val)?;
+        self.assumed.insert(name.to_string(), val);
+        Ok(())
+    }
+    /// Ends every `assume` override, uncovering the real values.
+    pub fn drop_assumptions(&mut self) {
+        self.assumed.clear();
+    }
+    /// Every visible binding, sorted by name. Inner scopes shadow outer ones.
+    pub fn vars(&self) -> Vec<(String, i32)> {
+        let mut visible: HashMap<&str, i32> = HashMap::new();
+        for scope in &self.scopes {
+            visible.extend(scope.iter().map(|(name, &val)| (name.as_str(), val)));
+        }
+        let mut vars: Vec<(String, i32)> = visible
+            .into_iter()
+            .map(|(name, val)| (name.to_string(), val))
+            .collect();
+        vars.sort();
+        vars
+    }
+    /// Assigns every variable visible in `other`, resolving names bound on
+    /// both sides by `policy`. Returns the names whose value was replaced,
+    /// sorted; under `MergePolicy::Keep` that is always empty.
+    pub fn merge(&mut self, other: Environment, policy: MergePolicy) -> Result<Vec<String>> {
+        let mut overwritten = Vec::new();
+        for (name, val) in other.vars() {
+            if self.contains(&name) {
+                if policy == MergePolicy::Keep {
+                    continue;
+                }
+                overwritten.push(name.clone());
+            }
+            self.assign(&name, val)?;
+            if let Some(source) = other.sources.get(&name) {
+                self.sources.insert(name, source.clone());
+            }
+        }
+        Ok(overwritten)
+    }
+    /// Binds `name` to an unevaluated expression. Reading `name` evaluates
+    /// `expr` against the variables at that moment; a variable with the same
+    /// name takes precedence.
+    pub fn define_alias(&mut self, name: &str, expr: Expr) {
+        self.aliases.insert(name.to_string(), expr);
+    }
+    pub fn alias(&self, name: &str) -> Option<&Expr> {
+        self.aliases.get(name)
+    }
+    /// The source of the expression that last assigned `name`, if it was set
+    /// by evaluating `name = ...` rather than through `assign` or `declare`.
+    pub fn source(&self, name: &str) -> Option<&str> {
+        self.sources.get(name).map(String::as_str)
+    }
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+    /// Discards the innermost scope and its locals. The global scope stays.
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+    /// Remembers a result for `ans`, forgetting the oldest once
+    /// `MAX_ANSWERS` are held.
+    pub fn record_answer(&mut self, val: i32) {
+        self.answers.push_front(val);
+        self.answers.truncate(MAX_ANSWERS);
+    }
+    /// The result `age` answers back: 1 is the latest, 2 the one before.
+    pub fn answer(&self, age: i32) -> Result<i32> {
+        usize::try_from(age)
+            .ok()
+            .and_then(|age| age.checked_sub(1))
+            .and_then(|i| self.answers.get(i))
+            .copied()
+            .ok_or_else(|| {
+                Error::MathError(format!(
+                    "ans({age}): {} answer(s) so far",
+                    self.answers.len()
+                ))
+            })
+    }
+    /// From now on, keeps what `print` is given for `take_printed` rather
+    /// than writing it to stdout, where it would get in the way of output
+    /// such as JSON.
+    pub fn capture_printed(&mut self) {
+        self.printed.get_or_insert_with(Vec::new);
+    }
+    /// The values printed since the last call, oldest first. Empty unless
+    /// `capture_printed` was called.
+    pub fn take_printed(&mut self) -> Vec<i32> {
+        self.printed
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+    /// What the `print` built-in does with `val`.
+    pub(crate) fn print(&mut self, val: i32) {
+        match &mut self.printed {
+            Some(printed) => printed.push(val),
+            None => println!("{val}"),
+        }
+    }
+    /// Everything but the `on_assign` hook and anything being collected,
+    /// such as a trace, in progress.
+    fn scratch_copy(&self) -> Environment {
+        Environment {
+            scopes: self.scopes.clone(),
+            aliases: self.aliases.clone(),
+            sources: self.sources.clone(),
+            assumed: self.assumed.clone(),
+            expanding: self.expanding.clone(),
+            division: self.division,
+            on_assign: None,
+            min_value: self.min_value,
+            max_value: self.max_value,
+            zero_default: self.zero_default,
+            trace: None,
+            skipped: None,
+            answers: self.answers.clone(),
+            rng: self.rng,
+            printed: None,
+        }
+    }
+    /// Restarts the `rand` sequence, so the same seed gives the same
+    /// numbers. A fresh environment is seeded from the clock.
+    pub fn seed(&mut self, seed: u64) {
+        // One splitmix64 step spreads small seeds over all the bits. The
+        // one seed it maps to 0 is bumped, since xorshift sticks at 0.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        self.rng = (z ^ (z >> 31)).max(1);
+    }
+    /// The next number of the `rand` sequence.
+    pub(crate) fn next_random(&mut self) -> u64 {
+        let mut x = self.rng;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    fn check_range(&self, val: i32) -> Result<()> {
+        if !(self.min_value..=self.max_value).contains(&val) {
+            return Err(Error::OutOfRange {
+                val,
+                min: self.min_value,
+                max: self.max_value,
+            });
+        }
+        Ok(())
+    }
+    fn top_scope(&mut self) -> &mut HashMap<String, i32> {
+        self.scopes
+            .last_mut()
+            .expect("the global scope is never popped")
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Each arm hands its work to a helper, so that the frame this function
+/// needs for every level of the tree stays small; see `MAX_HEIGHT`.
+pub fn evaluate(expr: &Expr, env: &mut Environment) -> Result<i32> {
+    let out = match expr {
+        Expr::Number { n } => Ok(*n),
+        Expr::Variable { name } => read_variable(name, env),
+        Expr::Assign { location, value } => evaluate_assign(location, value, env),
+        Expr::Compare { first, rest } => evaluate_compare(first, rest, env),
+        Expr::Add { left, right } => evaluate_binary(left, right, env, add),
+        Expr::Minus { left, right } => evaluate_binary(left, right, env, subtract),
+        Expr::Mul { left, right } => evaluate_binary(left, right, env, multiply),
+        Expr::Div { left, right } => evaluate_binary(left, right, env, divide),
+        Expr::Mod { left, right } => evaluate_binary(left, right, env, remainder),
+        Expr::Pow { left, right } => {
+            evaluate_binary(left, right, env, |base, exp, _| power(base, exp))
+        }
+        Expr::Call { name, args } => evaluate_call(name, args, env),
+        Expr::Neg { operand } => evaluate(operand, env).and_then(negate),
+        Expr::Factorial { operand } => evaluate(operand, env).and_then(factorial),
+        Expr::Not { operand } => evaluate(operand, env).map(|val| i32::from(val == 0)),
+        Expr::Group { inner } => evaluate(inner, env),
+        Expr::List { items } => evaluate_list(items, env),
+    }?;
+    if env.trace.is_some() {
+        record_step(expr, out, env);
+    }
+    Ok(out)
+}
+
+fn read_variable(name: &str, env: &mut Environment) -> Result<i32> {
+    if !env.contains(name) && env.aliases.contains_key(name) {
+        evaluate_alias(name, env, evaluate)
+    } else {
+        env.lookup(name)
+    }
+}
+
+fn evaluate_assign(location: &Expr, value: &Expr, env: &mut Environment) -> Result<i32> {
+    match location {
+        Expr::Variable { name } => {
+            let eval = evaluate(value, env)?;
+            env.assign(name, eval)?;
+            env.sources.insert(name.clone(), unparse(value));
+            env.lookup(name)
+        }
+        _ => Err(Error::SyntaxError(format!(
+            "can't assign to `{}`",
+            unparse(location)
+        ))),
+    }
+}
+
+fn evaluate_compare(
+    first: &Expr,
+    rest: &[(CompareOp, Expr)],
+    env: &mut Environment,
+) -> Result<i32> {
+    let mut left = evaluate(first, env)?;
+    for (i, (op, operand)) in rest.iter().enumerate() {
+        let right = evaluate(operand, env)?;
+        if !op.holds(left, right) {
+            if let Some(skipped) = &mut env.skipped {
+                *skipped += rest.len() - i - 1;
+            }
+            return Ok(0);
+        }
+        left = right;
+    }
+    Ok(1)
+}
+
+fn evaluate_binary(
+    left: &Expr,
+    right: &Expr,
+    env: &mut Environment,
+    op: fn(i32, i32, DivisionMode) -> Result<i32>,
+) -> Result<i32> {
+    let a = evaluate(left, env)?;
+    let b = evaluate(right, env)?;
+    op(a, b, env.division)
+}
+
+fn evaluate_call(name: &str, args: &[Expr], env: &mut Environment) -> Result<i32> {
+    builtins::check_arity(name, args.len())?;
+    if name == "repeat" {
+        return builtins::repeat(&args[0], &args[1], env);
+    }
+    let args = args
+        .iter()
+        .map(|arg| evaluate(arg, env))
+        .collect::<Result<Vec<i32>>>()?;
+    builtins::call(name, &args, env)
+}
+
+fn evaluate_list(items: &[Expr], env: &mut Environment) -> Result<i32> {
+    let mut last = 0;
+    for item in items {
+        last = evaluate(item, env)?;
+    }
+    Ok(last)
+}
+
+fn record_step(expr: &Expr, out: i32, env: &mut Environment) {
+    if let Some(trace) = &mut env.trace
+        && !matches!(expr, Expr::Number { .. } | Expr::Group { .. })
+    {
+        trace.push((unparse(expr), out));
+    }
+}
+
+/// Evaluates `expr` and also returns each sub-expression's value, in the
+/// order they were worked out, for a tape-style display. Literals and
+/// parentheses are left out since they'd only repeat themselves.
+pub fn evaluate_traced(expr: &Expr, env: &mut Environment) -> Result<(i32, Vec<(String, i32)>)> {
+    let outer = env.trace.replace(Vec::new());
+    let out = evaluate(expr, env);
+    let trace = std::mem::replace(&mut env.trace, outer).unwrap_or_default();
+    Ok((out?, trace))
+}
+
+/// Evaluates `expr` without being able to change `env`, refusing any
+/// expression that assigns. It runs against a scratch copy of `env`
+/// without the `on_assign` hook, so nothing an alias body does can leak
+/// back either.
+pub fn evaluate_readonly(expr: &Expr, env: &Environment) -> Result<i32> {
+    if let Some(Expr::Assign { location, .. }) = find_assign(expr) {
+        return Err(Error::SyntaxError(format!(
+            "can't assign {} in a read-only evaluation",
+            unparse(location)
+        )));
+    }
+    evaluate(expr, &mut env.scratch_copy())
+}
+
+fn find_assign(expr: &Expr) -> Option<&Expr> {
+    match expr {
+        Expr::Assign { .. } => Some(expr),
+        _ => expr.children().into_iter().find_map(find_assign),
+    }
+}
+
+/// Evaluates `expr` and also counts the operands short-circuiting skipped.
+/// A comparison chain stops at its first false link, so `1 > 2 > 1/0` is 0
+/// with one operand skipped and never divides by zero.
+pub fn evaluate_counted(expr: &Expr, env: &mut Environment) -> Result<(i32, usize)> {
+    let outer = env.skipped.replace(0);
+    let out = evaluate(expr, env);
+    let skipped = std::mem::replace(&mut env.skipped, outer).unwrap_or_default();
+    if let Some(outer) = &mut env.skipped {
+        *outer += skipped;
+    }
+    Ok((out?, skipped))
+}
+
+/// The operands `evaluate_counted` would skip, without touching `env`:
+/// `expr` runs on a scratch copy, so an assignment doesn't stick and
+/// `print` writes nothing.
+pub fn count_skipped(expr: &Expr, env: &Environment) -> Result<usize> {
+    let mut scratch = env.scratch_copy();
+    scratch.capture_printed();
+    Ok(evaluate_counted(expr, &mut scratch)?.1)
+}
+
+/// Evaluates `expr` and returns every value it produces: one per item of
+/// an `Expr::List`, two for a `reduce` call, or just the one for anything
+/// else.
+pub fn evaluate_all(expr: &Expr, env: &mut Environment) -> Result<Vec<i32>> {
+    match expr {
+        Expr::List { items } => {
+            let mut out = Vec::with_capacity(items.len());
+            for item in items {
+                out.extend(evaluate_all(item, env)?);
+            }
+            Ok(out)
+        }
+        Expr::Call { name, args } if name == "reduce" => {
+            builtins::check_arity(name, args.len())?;
+            let (n, d) = (evaluate(&args[0], env)?, evaluate(&args[1], env)?);
+            Ok(builtins::reduce(n, d)?.to_vec())
+        }
+        _ => Ok(vec![evaluate(expr, env)?]),
+    }
+}
+
+/// Evaluates every statement even when some fail, giving one result per
+/// statement so the caller can report all the errors rather than just the
+/// first. Later statements see the assignments of earlier ones that worked.
+pub fn try_evaluate(statements: &[Expr], env: &mut Environment) -> Vec<Result<i32>> {
+    statements.iter().map(|s| evaluate(s, env)).collect()
+}
+
+/// Renders the values from `evaluate_all`: a lone value as `format_result`
+/// does, several as a bracketed list such as `[2, 4, 9]`.
+pub fn format_values(vals: &[i32], group: bool) -> String {
+    match vals {
+        [val] => format_result(*val, group),
+        _ => {
+            let vals: Vec<String> = vals.iter().map(|&v| format_result(v, group)).collect();
+            format!("[{}]", vals.join(", "))
+        }
+    }
+}
+
+/// The 32 bits of `val`, most significant first, e.g. `0b00…01010` for 10.
+/// Negative values show in two's complement, so -1 is all ones.
+pub fn format_bits(val: i32) -> String {
+    format!("0b{val:032b}")
+}
+
+/// Renders a result for display. With `group` set, digits are split into
+/// thousands with commas, e.g. `-1234567` becomes `-1,234,567`.
+pub fn format_result(val: i32, group: bool) -> String {
+    if !group {
+        return val.to_string();
+    }
+    let digits = val.unsigned_abs().to_string();
+    let mut out = String::new();
+    if val < 0 {
+        out.push('-');
+    }
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Renders a result with an SI suffix and three significant digits once it
+/// reaches a thousand: `1500` is `1.5k`, `-2345678` is `-2.35M`. Smaller
+/// values print plainly. Results are whole numbers, so there are no
+/// sub-unit suffixes such as `m` or `µ`.
+pub fn format_si(val: i32) -> String {
+    const SCALES: [(f64, &str); 3] = [(1e3, "k"), (1e6, "M"), (1e9, "G")];
+    let Some(mut i) = SCALES
+        .iter()
+        .rposition(|&(scale, _)| f64::from(val).abs() >= scale)
+    else {
+        return val.to_string();
+    };
+    loop {
+        let (scale, suffix) = SCALES[i];
+        let scaled = f64::from(val) / scale;
+        let decimals = 2 - scaled.abs().log10().floor() as usize;
+        let text = format!("{scaled:.decimals$}");
+        // Rounding can carry up to 1000, as with 999999; that reads better
+        // as the next suffix up.
+        if text.trim_start_matches('-').starts_with("1000") && i + 1 < SCALES.len() {
+            i += 1;
+            continue;
+        }
+        let text = if text.contains('.') {
+            text.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            &text
+        };
+        return format!("{text}{suffix}");
+    }
+}
+
+/// Reads the alias `name` with `eval`, `evaluate` or its exact twin.
+fn evaluate_alias<T>(
+    name: &str,
+    env: &mut Environment,
+    eval: fn(&Expr, &mut Environment) -> Result<T>,
+) -> Result<T> {
+    if env.expanding.iter().any(|a| a == name) {
+        return Err(Error::SyntaxError(format!("alias {name} refers to itself")));
+    }
+    let expr = env.aliases[name].clone();
+    env.expanding.push(name.to_string());
+    let out = eval(&expr, env);
+    env.expanding.pop();
+    out
+}
+
+fn add(a: i32, b: i32, _: DivisionMode) -> Result<i32> {
+    a.checked_add(b)
+        .ok_or_else(|| Error::Overflow(format!("{a} + {b}")))
+}
+
+fn subtract(a: i32, b: i32, _: DivisionMode) -> Result<i32> {
+    a.checked_sub(b)
+        .ok_or_else(|| Error::Overflow(format!("{a} - {b}")))
+}
+
+fn multiply(a: i32, b: i32, _: DivisionMode) -> Result<i32> {
+    a.checked_mul(b)
+        .ok_or_else(|| Error::Overflow(format!("{a} * {b}")))
+}
+
+fn divide(a: i32, b: i32, mode: DivisionMode) -> Result<i32> {
+    if b == 0 {
+        return Err(Error::DivisionByZero);
+    }
+    let q = a
+        .checked_div(b)
+        .ok_or_else(|| Error::Overflow(format!("{a} / {b}")))?;
+    if mode == DivisionMode::Floored && a % b != 0 && (a < 0) != (b < 0) {
+        return Ok(q - 1);
+    }
+    Ok(q)
+}
+
+fn remainder(a: i32, b: i32, mode: DivisionMode) -> Result<i32> {
+    if b == 0 {
+        return Err(Error::DivisionByZero);
+    }
+    let r = a.checked_rem(b).unwrap_or(0);
+    if mode == DivisionMode::Floored && r != 0 && (r < 0) != (b < 0) {
+        return Ok(r + b);
+    }
+    Ok(r)
+}
+
+fn power(base: i32, exp: i32) -> Result<i32> {
+    let exp = u32::try_from(exp)
+        .map_err(|_| Error::MathError(format!("negative exponent {exp} in integer arithmetic")))?;
+    base.checked_pow(exp)
+        .ok_or_else(|| Error::Overflow(format!("{base} ^ {exp}")))
+}
+
+fn negate(val: i32) -> Result<i32> {
+    val.checked_neg()
+        .ok_or_else(|| Error::Overflow(format!("-({val})")))
+}
+
+fn factorial(n: i32) -> Result<i32> {
+    if n < 0 {
+        return Err(Error::MathError(format!(
+            "factorial of negative number {n}"
+        )));
+    }
+    (1..=n).try_fold(1i32, |acc, k| {
+        acc.checked_mul(k)
+            .ok_or_else(|| Error::Overflow(format!("{n}!")))
+    })
+}
+
+/// A "did you mean" hint for an unknown name: the closest variable or
+/// alias in `env` for `UndefinedVariable`, or built-in for
+/// `UnknownFunction`. `None` for other errors or when nothing is close.
+pub fn suggest_fix(e: &Error, env: &Environment) -> Option<String> {
+    let suggestion = match e {
+        Error::UndefinedVariable(name) => {
+            let vars = env.vars();
+            let known = vars.iter().map(|(var, _)| var.as_str());
+            closest_name(name, known.chain(env.aliases.keys().map(String::as_str)))
+                .map(str::to_string)
+        }
+        Error::UnknownFunction(name) => closest_name(
+            name,
+            builtins::BUILTINS.iter().map(|&(builtin, _, _)| builtin),
+        )
+        .map(str::to_string),
+        _ => None,
+    }?;
+    Some(format!("did you mean `{suggestion}`?"))
+}
+
+/// The candidate fewest single-character edits from `name`, as long as
+/// that's at most 2 and fewer than `name` has characters, so `x` doesn't
+/// suggest `y`. Ties go to the alphabetically first.
+pub fn closest_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let limit = 2.min(name.chars().count().saturating_sub(1));
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance: insertions, deletions and substitutions needed
+/// to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+pub const DEFAULT_MAX_INPUT_BYTES: usize = 1024 * 1024;
+
+/// Rejects inputs longer than `max` bytes before any tokenizing happens.
+pub fn check_input_len(source: &str, max: usize) -> Result<()> {
+    if source.len() > max {
+        return Err(Error::InputTooLong {
+            len: source.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// Runs the whole tokenize/parse/evaluate pipeline on one expression.
+///
+/// This never panics, whatever `source` holds: bad syntax, undefined names,
+/// overflow, division by zero and over-deep or over-long expressions all come
+/// back as `Err`.
+pub fn eval_str(source: &str, env: &mut Environment) -> Result<i32> {
+    let tokens = tokenize(source)?;
+    let mut p = Parser::new(tokens);
+    let expr = parse(&mut p)?;
+    evaluate(&expr, env)
+}
+
+/// Runs `eval_str` and reports how long the whole pipeline took.
+pub fn eval_timed(source: &str, env: &mut Environment) -> (Result<i32>, Duration) {
+    let start = Instant::now();
+    let out = eval_str(source, env);
+    (out, start.elapsed())
+}
+
+/// Time spent in each phase of `eval_str`. Phases that didn't run because
+/// an earlier one failed stay at zero.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PhaseTimes {
+    pub tokenize: Duration,
+    pub parse: Duration,
+    pub evaluate: Duration,
+}
+
+/// Runs `eval_str` one phase at a time, timing each separately.
+pub fn eval_profiled(source: &str, env: &mut Environment) -> (Result<i32>, PhaseTimes) {
+    let mut times = PhaseTimes::default();
+    let start = Instant::now();
+    let tokens = tokenize(source);
+    times.tokenize = start.elapsed();
+    let tokens = match tokens {
+        Ok(tokens) => tokens,
+        Err(e) => return (Err(e), times),
+    };
+    let start = Instant::now();
+    let expr = parse(&mut Parser::new(tokens));
+    times.parse = start.elapsed();
+    let expr = match expr {
+        Ok(expr) => expr,
+        Err(e) => return (Err(e), times),
+    };
+    let start = Instant::now();
+    let out = evaluate(&expr, env);
+    times.evaluate = start.elapsed();
+    (out, times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> Result<i32> {
+        eval_str(source, &mut Environment::new())
+    }
+
+    fn error_message(source: &str) -> String {
+        match eval(source) {
+            Ok(val) => panic!("{source} evaluated to {val}"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn literal_above_i32_max_is_out_of_range() {
+        let msg = error_message("2147483648");
+        assert!(msg.contains("out of range for i32"), "{msg}");
+    }
+
+    #[test]
+    fn literal_in_range_parses() {
+        assert_eq!(eval("2147483647").unwrap(), i32::MAX);
+        assert_eq!(eval("42").unwrap(), 42);
+    }
+
+    #[test]
+    fn define_and_undefine_for_embedding() {
+        let mut env = Environment::new();
+        assert!(!env.contains("width"));
+        env.define("width", 6).unwrap();
+        env.define("height", 7).unwrap();
+        assert!(env.contains("width"));
+        assert_eq!(eval_str("width * height", &mut env).unwrap(), 42);
+        env.undefine("width");
+        assert!(!env.contains("width"));
+        assert!(matches!(
+            eval_str("width * height", &mut env),
+            Err(Error::UndefinedVariable(name)) if name == "width"
+        ));
+    }
+
+    #[test]
+    fn grouping_splits_thousands() {
+        assert_eq!(format_result(1_000_000, true), "1,000,000");
+        assert_eq!(format_result(1234, true), "1,234");
+        assert_eq!(format_result(-1000, true), "-1,000");
+        assert_eq!(format_result(i32::MIN, true), "-2,147,483,648");
+    }
+
+    #[test]
+    fn grouping_leaves_small_numbers_alone() {
+        assert_eq!(format_result(999, true), "999");
+        assert_eq!(format_result(-12, true), "-12");
+        assert_eq!(format_result(0, true), "0");
+        assert_eq!(format_result(1_000_000, false), "1000000");
+    }
+
+    #[test]
+    fn format_tokens_is_compact() {
+        let tokens = tokenize("2 + x * (10)").unwrap();
+        assert_eq!(
+            format_tokens(&tokens),
+            "[Num(2), Plus, Name(x), Times, Lparen, Num(10), Rparen]"
+        );
+    }
+
+    #[test]
+    fn small_literals_borrow_and_keep_their_text() {
+        for (source, val) in [("0", 0), ("9", 9), ("10", 10), ("007", 7)] {
+            let tokens = tokenize(source).unwrap();
+            assert_eq!(tokens[0].token_type, TokenType::Num);
+            assert_eq!(tokens[0].val, source);
+            assert_eq!(
+                matches!(tokens[0].val, Cow::Borrowed(_)),
+                source.len() == 1,
+                "{source}"
+            );
+            assert_eq!(eval_str(source, &mut Environment::new()).unwrap(), val);
+        }
+    }
+
+    #[test]
+    fn borrowed_operator_tokens_render_as_before() {
+        let tokens = tokenize("1 + 2 ** 3 <= 4").unwrap();
+        for token in &tokens[..tokens.len() - 1] {
+            if token.token_type != TokenType::Num {
+                assert!(matches!(token.val, Cow::Borrowed(_)), "{token}");
+            }
+        }
+        assert_eq!(
+            format_tokens(&tokens),
+            "[Num(1), Plus, Num(2), Pow, Num(3), Le, Num(4)]"
+        );
+        assert_eq!(
+            tokens[1].to_string(),
+            r#"Token { token_type: Plus, val: "+", span: Span { start: 2, end: 3 } }"#
+        );
+        assert_eq!(
+            tokens_to_json(&tokens[3..4]),
+            r#"[{"type":"Pow","text":"**","start":6,"end":8}]"#
+        );
+    }
+
+    fn parse_str(source: &str) -> Result<Expr> {
+        parse(&mut Parser::new(tokenize(source)?))
+    }
+
+    fn round_trip(source: &str, keep_groups: bool) -> String {
+        let tokens = tokenize(source).unwrap();
+        unparse(&parse(&mut Parser::new(tokens).keep_groups(keep_groups)).unwrap())
+    }
+
+    #[test]
+    fn groups_are_kept_only_when_asked() {
+        assert_eq!(round_trip("(2 + 3)", true), "(2 + 3)");
+        assert_eq!(round_trip("(2 + 3)", false), "2 + 3");
+        assert_eq!(round_trip("((4)) * 2", true), "((4)) * 2");
+        assert_eq!(round_trip("((4)) * 2", false), "4 * 2");
+        assert_eq!(round_trip("(2 + 3) * 4", false), "(2 + 3) * 4");
+    }
+
+    #[test]
+    fn groups_evaluate_transparently() {
+        let tokens = tokenize("(2 + 3) * 4").unwrap();
+        let expr = parse(&mut Parser::new(tokens).keep_groups(true)).unwrap();
+        assert!(matches!(&expr, Expr::Mul { left, .. } if matches!(**left, Expr::Group { .. })));
+        assert_eq!(evaluate(&expr, &mut Environment::new()).unwrap(), 20);
+        assert_ne!(expr, parse_str("(2 + 3) * 4").unwrap());
+    }
+
+    #[test]
+    fn eval_timed_returns_value_and_duration() {
+        let mut env = Environment::new();
+        let (out, elapsed) = eval_timed("repeat(2 * 3, 100)", &mut env);
+        assert_eq!(out.unwrap(), 600);
+        assert!(elapsed > Duration::ZERO);
+        let (out, _) = eval_timed("1 / 0", &mut env);
+        assert!(matches!(out, Err(Error::DivisionByZero)));
+    }
+
+    #[test]
+    fn empty_parentheses_are_named() {
+        assert_eq!(error_message("()"), "SyntaxError: empty parentheses");
+        assert_eq!(error_message("2 * []"), "SyntaxError: empty brackets");
+    }
+
+    /// The parsed tree of `source` as an S-expression, which shows every
+    /// grouping explicitly.
+    fn tree(source: &str) -> String {
+        sexpr::to_sexpr(&parse_str(source).unwrap())
+    }
+
+    #[test]
+    fn precedence_table_drives_the_lookups() {
+        assert_eq!(
+            binary_precedence(TokenType::Times),
+            Some((PREC_PRODUCT, Assoc::Left))
+        );
+        assert_eq!(
+            binary_precedence(TokenType::Pow),
+            Some((PREC_POW, Assoc::Right))
+        );
+        assert_eq!(prefix_precedence(TokenType::Minus), Some(PREC_PREFIX));
+        assert_eq!(postfix_precedence(TokenType::Bang), Some(PREC_POSTFIX));
+        assert_eq!(binary_precedence(TokenType::Bang), None);
+        let tokens = tokenize("x + (1)").unwrap();
+        let operators: Vec<bool> = tokens.iter().map(Token::is_operator).collect();
+        assert_eq!(operators, [false, true, false, false, false, false]);
+    }
+
+    #[test]
+    fn mixed_precedence_trees() {
+        assert_eq!(tree("2 + 3 * 4"), "(+ 2 (* 3 4))");
+        assert_eq!(tree("2 * 3 + 4"), "(+ (* 2 3) 4)");
+        assert_eq!(tree("8 - 3 - 2"), "(- (- 8 3) 2)");
+        assert_eq!(tree("2 * 3 % 4 / 5"), "(/ (% (* 2 3) 4) 5)");
+    }
+
+    #[test]
+    fn pratt_operator_matrix() {
+        let cases = [
+            ("2 ^ 3 ^ 2", "(^ 2 (^ 3 2))"),
+            ("-2 ^ 2", "(- (^ 2 2))"),
+            ("-x * 3", "(* (- x) 3)"),
+            ("-3!", "(- (! 3))"),
+            ("2 * 3!", "(* 2 (! 3))"),
+            ("3! ^ 2", "(^ (! 3) 2)"),
+            ("2 ^ 3!", "(^ 2 (! 3))"),
+            ("--x", "(- (- x))"),
+            ("+x", "x"),
+            ("not x + 1", "(+ (not x) 1)"),
+            ("not x < 1", "(< (not x) 1)"),
+            ("1 + 2 < 3 * 4", "(< (+ 1 2) (* 3 4))"),
+            ("a = b = 2 + 1", "(= a (= b (+ 2 1)))"),
+            ("x = 1 < 2", "(= x (< 1 2))"),
+            ("(2 + 3) * 4", "(* (+ 2 3) 4)"),
+            ("2 * (3 + 4) ^ 2", "(* 2 (^ (+ 3 4) 2))"),
+            ("max(1 + 2, 3) * 2", "(* (max (+ 1 2) 3) 2)"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(tree(source), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn input_length_guard() {
+        assert!(check_input_len("2 + 3", 5).is_ok());
+        assert!(matches!(
+            check_input_len("2 + 33", 5),
+            Err(Error::InputTooLong { len: 6, max: 5 })
+        ));
+        let long = "1+".repeat(DEFAULT_MAX_INPUT_BYTES);
+        assert!(check_input_len(&long, DEFAULT_MAX_INPUT_BYTES).is_err());
+    }
+
+    #[test]
+    fn inner_scopes_shadow_and_fall_through() {
+        let mut env = Environment::new();
+        env.assign("x", 1).unwrap();
+        env.assign("y", 2).unwrap();
+        env.push_scope();
+        env.declare("x", 10).unwrap();
+        assert_eq!(env.lookup("x").unwrap(), 10);
+        assert_eq!(env.lookup("y").unwrap(), 2);
+        env.assign("y", 20).unwrap();
+        env.assign("z", 30).unwrap();
+        env.pop_scope();
+        assert_eq!(env.lookup("x").unwrap(), 1);
+        assert_eq!(env.lookup("y").unwrap(), 20);
+        assert!(!env.contains("z"));
+    }
+
+    #[test]
+    fn global_scope_is_never_popped() {
+        let mut env = Environment::new();
+        env.assign("x", 1).unwrap();
+        env.pop_scope();
+        assert_eq!(env.lookup("x").unwrap(), 1);
+    }
+
+    #[test]
+    fn truncated_and_floored_modulo() {
+        let mut env = Environment::new();
+        assert_eq!(eval_str("-7 % 3", &mut env).unwrap(), -1);
+        assert_eq!(eval_str("-7 / 3", &mut env).unwrap(), -2);
+        env.division = DivisionMode::Floored;
+        assert_eq!(eval_str("-7 % 3", &mut env).unwrap(), 2);
+        assert_eq!(eval_str("-7 / 3", &mut env).unwrap(), -3);
+        assert_eq!(eval_str("7 % -3", &mut env).unwrap(), -2);
+        assert_eq!(eval_str("6 % 3", &mut env).unwrap(), 0);
+    }
+
+    #[test]
+    fn trailing_operator_names_the_operator() {
+        assert_eq!(
+            error_message("2 +"),
+            "SyntaxError: expected expression after `+`"
+        );
+        assert_eq!(
+            error_message("(4 *"),
+            "SyntaxError: expected expression after `*`"
+        );
+    }
+
+    #[test]
+    fn negating_i32_min_overflows() {
+        assert_eq!(eval("-2147483648").unwrap(), i32::MIN);
+        assert!(matches!(eval("-(-2147483648)"), Err(Error::Overflow(_))));
+        let mut env = Environment::new();
+        env.define("x", i32::MIN).unwrap();
+        assert!(matches!(eval_str("-x", &mut env), Err(Error::Overflow(_))));
+    }
+
+    #[test]
+    fn substitute_into_arithmetic() {
+        let replacement = parse_str("y + 1").unwrap();
+        let out = substitute(parse_str("x * 2 + x").unwrap(), "x", &replacement);
+        assert_eq!(unparse(&out), "(y + 1) * 2 + (y + 1)");
+        let untouched = substitute(parse_str("z * 2").unwrap(), "x", &replacement);
+        assert_eq!(untouched, parse_str("z * 2").unwrap());
+    }
+
+    #[test]
+    fn substitute_leaves_assignment_targets() {
+        let replacement = parse_str("3").unwrap();
+        let out = substitute(parse_str("x = x + 1").unwrap(), "x", &replacement);
+        assert_eq!(unparse(&out), "x = 3 + 1");
+    }
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn check_passes_a_clean_script() {
+        let defined = names(&["a"]);
+        assert!(check(&parse_str("(x = a + 2) + x").unwrap(), &defined).is_ok());
+        assert!(check(&parse_str("max(a, 1)").unwrap(), &defined).is_ok());
+    }
+
+    #[test]
+    fn check_lists_reads_before_assignment() {
+        let expr = parse_str("x + y * y + (y = 1)").unwrap();
+        match check(&expr, &names(&[])) {
+            Err(Error::UndefinedVariables(undefined)) => assert_eq!(undefined, ["x", "y"]),
+            other => panic!("expected undefined variables, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn token_stream_ends_with_eof() {
+        for source in ["", "  ", "2 + 3", "# only a comment"] {
+            let tokens = tokenize(source).unwrap();
+            let last = tokens.last().unwrap();
+            assert_eq!(last.token_type, TokenType::Eof, "{source:?}");
+            assert_eq!(last.span.start, source.chars().count());
+            assert_eq!(
+                tokens
+                    .iter()
+                    .filter(|t| t.token_type == TokenType::Eof)
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn parser_stops_at_eof() {
+        assert_eq!(eval("2 + 3").unwrap(), 5);
+        assert_eq!(
+            error_message("2 3"),
+            "SyntaxError: Unprocessed characters remain. First unprocessed: 3"
+        );
+        assert_eq!(
+            error_message("(2"),
+            "SyntaxError: ( not closed by a ). Found ( Number { n: 2 } "
+        );
+        // A parser given tokens without an `Eof` gets one appended.
+        let mut tokens = tokenize("1 + 1").unwrap();
+        tokens.pop();
+        assert_eq!(
+            evaluate(
+                &parse(&mut Parser::new(tokens)).unwrap(),
+                &mut Environment::new()
+            )
+            .unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn double_star_is_power() {
+        assert_eq!(eval("2 ** 10").unwrap(), 1024);
+        assert_eq!(
+            parse_str("2 ** 3 ^ 2").unwrap(),
+            parse_str("2 ^ 3 ^ 2").unwrap()
+        );
+    }
+
+    #[test]
+    fn spaced_stars_are_not_power() {
+        assert!(matches!(eval("2 * * 3"), Err(Error::SyntaxError(_))));
+        assert_eq!(eval("2 * 3").unwrap(), 6);
+    }
+
+    #[test]
+    fn unexpected_character_reports_its_column() {
+        match tokenize("2 @ 3") {
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "SyntaxError: unexpected character '@' at column 3"
+            ),
+            Ok(tokens) => panic!("tokenized to {}", format_tokens(&tokens)),
+        }
+    }
+
+    #[test]
+    fn columns_count_chars_not_bytes() {
+        match tokenize("é + ü") {
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "SyntaxError: unexpected character 'é' at column 1"
+            ),
+            Ok(tokens) => panic!("tokenized to {}", format_tokens(&tokens)),
+        }
+        match tokenize("1 + é") {
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "SyntaxError: unexpected character 'é' at column 5"
+            ),
+            Ok(tokens) => panic!("tokenized to {}", format_tokens(&tokens)),
+        }
+    }
+
+    #[test]
+    fn aliases_follow_their_variables() {
+        let mut env = Environment::new();
+        env.define_alias("area", parse_str("w * h").unwrap());
+        env.define("w", 2).unwrap();
+        env.define("h", 3).unwrap();
+        assert_eq!(eval_str("area", &mut env).unwrap(), 6);
+        env.define("w", 10).unwrap();
+        assert_eq!(eval_str("area + 1", &mut env).unwrap(), 31);
+    }
+
+    #[test]
+    fn self_referencing_alias_is_an_error() {
+        let mut env = Environment::new();
+        env.define_alias("loop", parse_str("loop + 1").unwrap());
+        assert!(matches!(
+            eval_str("loop", &mut env),
+            Err(Error::SyntaxError(_))
+        ));
+    }
+
+    #[test]
+    fn literal_bounds_are_named() {
+        let msg = error_message("3000000000");
+        assert!(msg.contains("above the maximum 2147483647"), "{msg}");
+        let msg = error_message("-2147483649");
+        assert!(msg.contains("below the minimum -2147483648"), "{msg}");
+        assert_eq!(eval("-2147483648").unwrap(), i32::MIN);
+    }
+
+    #[test]
+    fn negative_literals_unparse_as_prefix_minus() {
+        let cases = [
+            ("(-2) ^ 2", "(-2) ^ 2"),
+            ("(-3)!", "(-3)!"),
+            ("-(-2)", "-(-2)"),
+            ("2 - -3", "2 - -3"),
+            ("-2 * 3", "-2 * 3"),
+            ("-2 ^ 2", "-2 ^ 2"),
+            ("x = -5", "x = -5"),
+        ];
+        for (source, expected) in cases {
+            let expr = parse_str(source).unwrap();
+            let text = unparse(&expr);
+            assert_eq!(text, expected, "{source}");
+            assert_eq!(parse_str(&text).unwrap(), expr, "{source}");
+        }
+        assert_eq!(eval("(-2) ^ 2").unwrap(), 4);
+        assert_eq!(eval(&unparse(&parse_str("(-2) ^ 2").unwrap())).unwrap(), 4);
+    }
+
+    /// Asserts that every spelling in `variants` tokenizes to the same
+    /// sequence of token types, whatever its whitespace.
+    fn assert_same_token_types(variants: &[&str]) {
+        let types = |source: &str| -> Vec<TokenType> {
+            tokenize(source)
+                .unwrap()
+                .iter()
+                .map(|t| t.token_type)
+                .collect()
+        };
+        let expected = types(variants[0]);
+        for variant in &variants[1..] {
+            assert_eq!(types(variant), expected, "{variant:?} vs {:?}", variants[0]);
+        }
+    }
+
+    #[test]
+    fn whitespace_is_insignificant() {
+        let cases: &[&[&str]] = &[
+            &["2+3", "2 + 3", "  2  +  3 "],
+            &["x=max(1,2)", "x = max( 1 , 2 )", "\tx\t=\nmax (1,\n2)"],
+            &["-3!*2", "- 3 ! * 2", " -3! *2 "],
+            &["1<=2==1", "1 <= 2 == 1", "1<= 2 ==1"],
+            &["2**3", " 2 ** 3 "],
+        ];
+        for variants in cases {
+            assert_same_token_types(variants);
+        }
+    }
+
+    #[test]
+    fn comparison_chains_read_like_maths() {
+        assert_eq!(eval("1 < 2 < 3").unwrap(), 1);
+        assert_eq!(eval("3 < 2 < 1").unwrap(), 0);
+        // C would read this as (3 > 2) > 1, i.e. 1 > 1.
+        assert_eq!(eval("3 > 2 > 1").unwrap(), 1);
+        assert_eq!(eval("(3 > 2) > 1").unwrap(), 0);
+    }
+
+    #[test]
+    fn chain_evaluates_middle_operand_once() {
+        let mut env = Environment::new();
+        env.define("n", 0).unwrap();
+        assert_eq!(eval_str("0 < (n = n + 1) < 5", &mut env).unwrap(), 1);
+        assert_eq!(env.lookup("n").unwrap(), 1);
+    }
+
+    fn explain_str(source: &str) -> String {
+        explain(&parse_str(source).unwrap())
+    }
+
+    #[test]
+    fn explain_follows_precedence() {
+        assert_eq!(explain_str("2 + 3 * 4"), "add 2 to (multiply 3 by 4)");
+        assert_eq!(explain_str("(2 + 3) * 4"), "multiply (add 2 to 3) by 4");
+        assert_eq!(explain_str("10 - 2"), "subtract 2 from 10");
+    }
+
+    #[test]
+    fn explain_assignment_and_calls() {
+        assert_eq!(explain_str("x = 1 + 2"), "set x to (add 1 to 2)");
+        assert_eq!(explain_str("max(x, 2)"), "call max with x, 2");
+        assert_eq!(explain_str("-x!"), "negate (the factorial of x)");
+    }
+
+    #[test]
+    fn leading_sign_is_unary() {
+        assert_eq!(eval("+ 3").unwrap(), 3);
+        assert_eq!(eval("- 3").unwrap(), -3);
+    }
+
+    #[test]
+    fn leading_binary_operator_is_refused() {
+        assert_eq!(
+            error_message("* 5"),
+            "SyntaxError: expression cannot start with `*`"
+        );
+        assert_eq!(
+            error_message("/ 5"),
+            "SyntaxError: expression cannot start with `/`"
+        );
+    }
+
+    #[test]
+    fn on_assign_sees_each_stored_value() {
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut env = Environment::new();
+        let log = std::rc::Rc::clone(&seen);
+        env.on_assign = Some(Box::new(move |name, val| {
+            log.borrow_mut().push((name.to_string(), val))
+        }));
+        eval_str("x = 4", &mut env).unwrap();
+        eval_str("y = x * 2", &mut env).unwrap();
+        eval_str("x + y", &mut env).unwrap();
+        assert_eq!(*seen.borrow(), [("x".to_string(), 4), ("y".to_string(), 8)]);
+    }
+
+    #[test]
+    fn dot_has_a_node_per_expr_and_an_edge_per_child() {
+        let dot = expr_to_dot(&parse_str("2 + 3 * 4").unwrap());
+        let nodes = dot.lines().filter(|l| l.contains("[label=")).count();
+        let edges: Vec<&str> = dot
+            .lines()
+            .filter(|l| l.contains("->"))
+            .map(str::trim)
+            .collect();
+        assert_eq!(nodes, 5);
+        assert_eq!(edges, ["n0 -> n1;", "n2 -> n3;", "n2 -> n4;", "n0 -> n2;"]);
+        assert!(dot.starts_with("digraph ast {"));
+        assert!(dot.contains(r#"n0 [label="+"];"#));
+        assert!(dot.contains(r#"n2 [label="*"];"#));
+    }
+
+    #[test]
+    fn try_evaluate_reports_every_statement() {
+        let statements = parse_program(&mut Parser::new(
+            tokenize("x = 2; 1 / 0; x * 3; y").unwrap(),
+        ))
+        .unwrap();
+        let results = try_evaluate(&statements, &mut Environment::new());
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap(), &2);
+        assert!(matches!(results[1], Err(Error::DivisionByZero)));
+        assert_eq!(results[2].as_ref().unwrap(), &6);
+        assert!(matches!(&results[3], Err(Error::UndefinedVariable(name)) if name == "y"));
+    }
+
+    #[test]
+    fn adjacent_operators_are_reported() {
+        assert_eq!(
+            error_message("2 ++ 3"),
+            "SyntaxError: unexpected operator after operator: `+` then `+`"
+        );
+        assert_eq!(
+            error_message("2 * * 3"),
+            "SyntaxError: unexpected operator after operator: `*` then `*`"
+        );
+        assert_eq!(eval("2 * -3").unwrap(), -6);
+    }
+
+    #[test]
+    fn brackets_group_like_parentheses() {
+        assert_eq!(eval("[2 + 3] * 4").unwrap(), 20);
+        assert_eq!(eval("[(1 + 1) * [2 + 1]]").unwrap(), 6);
+    }
+
+    #[test]
+    fn brackets_must_match() {
+        assert_eq!(
+            error_message("(2 + 3]"),
+            "SyntaxError: mismatched brackets: ( closed by ]"
+        );
+        assert_eq!(
+            error_message("[2 + 3)"),
+            "SyntaxError: mismatched brackets: [ closed by )"
+        );
+    }
+
+    #[test]
+    fn height_counts_tree_levels_not_operators() {
+        let sum = vec!["1"; MAX_NESTING * 2].join(" + ");
+        assert_eq!(eval(&sum).unwrap(), (MAX_NESTING * 2) as i32);
+        // The links of a comparison chain sit side by side in one node.
+        let chain = vec!["1"; MAX_HEIGHT * 2].join(" <= ");
+        assert_eq!(eval(&chain).unwrap(), 1);
+        let message = error_message(&format!("1{}", "!".repeat(MAX_HEIGHT)));
+        assert!(message.contains("expression too long"), "{message}");
+    }
+
+    #[test]
+    fn profiled_evaluation_matches_eval_str() {
+        let (out, _) = eval_profiled("x = 2 + 3 * 4", &mut Environment::new());
+        assert_eq!(out.unwrap(), 14);
+        let (out, times) = eval_profiled("2 +", &mut Environment::new());
+        assert!(matches!(out, Err(Error::SyntaxError(_))));
+        // Evaluation never started, so it took no time at all.
+        assert_eq!(times.evaluate, Duration::ZERO);
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        let implied = |source: &str, env: &mut Environment| {
+            let tokens = tokenize(source).unwrap();
+            let expr = parse(&mut Parser::new(tokens).implicit_mul(true)).unwrap();
+            evaluate(&expr, env).unwrap()
+        };
+        let mut env = Environment::new();
+        env.assign("x", 5).unwrap();
+        assert_eq!(implied("2(3 + 4)", &mut env), 14);
+        assert_eq!(implied("2x", &mut env), 10);
+        assert_eq!(implied("(1 + 1)(2 + 2)", &mut env), 8);
+        assert_eq!(implied("abs(-3)", &mut env), 3);
+        // Off by default.
+        assert!(eval("2(3 + 4)").is_err());
+    }
+
+    #[test]
+    fn lookup_or_falls_back_only_for_missing_names() {
+        let mut env = Environment::new();
+        env.assign("x", 4).unwrap();
+        assert_eq!(env.lookup_or("x", 9), 4);
+        assert_eq!(env.lookup_or("y", 9), 9);
+        assert!(matches!(env.lookup("y"), Err(Error::UndefinedVariable(_))));
+    }
+
+    #[test]
+    fn comments_are_kept_only_when_asked_for() {
+        let kept = tokenize_with_comments("2 + 3 # note").unwrap();
+        let types: Vec<TokenType> = kept.iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Num,
+                TokenType::Plus,
+                TokenType::Num,
+                TokenType::Comment,
+                TokenType::Eof
+            ]
+        );
+        assert_eq!(kept[3].val, "# note");
+        let skipped = tokenize("2 + 3 # note").unwrap();
+        assert!(skipped.iter().all(|t| t.token_type != TokenType::Comment));
+        // The parser ignores comment tokens, wherever they sit.
+        assert_eq!(
+            evaluate(
+                &parse(&mut Parser::new(kept)).unwrap(),
+                &mut Environment::new()
+            )
+            .unwrap(),
+            5
+        );
+    }
+
+    #[test]
+    fn top_level_commas_make_a_list() {
+        let all = |source: &str| {
+            let expr = parse_str(source).unwrap();
+            evaluate_all(&expr, &mut Environment::new()).unwrap()
+        };
+        assert_eq!(all("1 + 1, 2 * 2"), [2, 4]);
+        assert_eq!(all("1 + 1, 2 * 2, 3 ^ 2"), [2, 4, 9]);
+        assert_eq!(
+            format_values(&all("1 + 1, 2 * 2, 3 ^ 2"), false),
+            "[2, 4, 9]"
+        );
+        // Commas inside a call's parentheses are its arguments instead.
+        assert_eq!(all("max(1, 2)"), [2]);
+        assert_eq!(format_values(&all("7"), false), "7");
+    }
+
+    #[test]
+    fn assignments_must_fall_within_the_bounds() {
+        let mut env = Environment::new();
+        env.min_value = -10;
+        env.max_value = 10;
+        assert_eq!(eval_str("x = 10", &mut env).unwrap(), 10);
+        assert_eq!(eval_str("x = -10", &mut env).unwrap(), -10);
+        assert!(matches!(
+            eval_str("x = 11", &mut env),
+            Err(Error::OutOfRange {
+                val: 11,
+                min: -10,
+                max: 10
+            })
+        ));
+        // A refused assignment leaves the old value in place.
+        assert_eq!(env.lookup("x").unwrap(), -10);
+        assert_eq!(eval("x = 2147483647").unwrap(), i32::MAX);
+    }
+
+    #[test]
+    fn token_counts_for_a_mixed_input() {
+        let counts = token_type_counts(&tokenize("x = (2 + 3) * x + 1").unwrap());
+        assert_eq!(counts[&TokenType::Name], 2);
+        assert_eq!(counts[&TokenType::Num], 3);
+        assert_eq!(counts[&TokenType::Plus], 2);
+        assert_eq!(counts[&TokenType::Times], 1);
+        assert_eq!(counts[&TokenType::Assign], 1);
+        assert_eq!(counts[&TokenType::Lparen], 1);
+        assert_eq!(counts[&TokenType::Eof], 1);
+        assert!(!counts.contains_key(&TokenType::Slash));
+    }
+
+    #[test]
+    fn every_token_type_hashes_distinctly() {
+        use TokenType::*;
+        let all = [
+            Num, Name, Plus, Minus, Times, Lparen, Rparen, Lbracket, Rbracket, Assign, Not,
+            Semicolon, Comment, Comma, Bang, Slash, Percent, Pow, Lt, Le, Gt, Ge, EqEq, NotEq, Eof,
+        ];
+        // Fails to compile when a variant is added, as a reminder to list it above.
+        for token_type in all {
+            match token_type {
+                Num | Name | Plus | Minus | Times | Lparen | Rparen | Lbracket | Rbracket
+                | Assign | Not | Semicolon | Comment | Comma | Bang | Slash | Percent | Pow
+                | Lt | Le | Gt | Ge | EqEq | NotEq | Eof => {}
+            }
+        }
+        let mut set: HashSet<TokenType> = all.into_iter().collect();
+        assert_eq!(set.len(), all.len());
+        assert!(!set.insert(Plus));
+        assert_eq!(set.len(), all.len());
+    }
+
+    #[test]
+    fn stats_of_a_known_expression() {
+        assert_eq!(
+            expr_stats("2 + 3 * (4 - 1)").unwrap(),
+            Stats {
+                tokens: 9,
+                operators: 3,
+                operands: 4,
+                paren_depth: 1,
+                nodes: 7,
+            }
+        );
+        assert_eq!(expr_stats("[(x)]").unwrap().paren_depth, 2);
+        assert!(expr_stats("2 +").is_err());
+    }
+
+    #[test]
+    fn answers_by_age_and_their_bounds() {
+        let mut env = Environment::new();
+        assert!(env.answer(1).is_err());
+        for val in 1..=(MAX_ANSWERS as i32 + 5) {
+            env.record_answer(val);
+        }
+        let latest = MAX_ANSWERS as i32 + 5;
+        assert_eq!(env.answer(1).unwrap(), latest);
+        assert_eq!(env.answer(2).unwrap(), latest - 1);
+        assert_eq!(eval_str("ans(3)", &mut env).unwrap(), latest - 2);
+        // Only the last MAX_ANSWERS are kept.
+        assert_eq!(env.answer(MAX_ANSWERS as i32).unwrap(), 6);
+        for age in [0, -1, MAX_ANSWERS as i32 + 1] {
+            let message = env.answer(age).unwrap_err().to_string();
+            assert!(message.contains("100 answer(s) so far"), "{message}");
+        }
+    }
+
+    #[test]
+    fn unassigned_names_read_as_zero_only_when_asked() {
+        let mut env = Environment::new();
+        assert!(matches!(
+            eval_str("y + 1", &mut env),
+            Err(Error::UndefinedVariable(_))
+        ));
+        env.zero_default = true;
+        assert_eq!(eval_str("y + 1", &mut env).unwrap(), 1);
+        assert!(!env.contains("y"));
+    }
+
+    #[test]
+    fn traced_evaluation_lists_each_step_in_order() {
+        let expr = parse_str("2 + 3 * 4").unwrap();
+        let (out, steps) = evaluate_traced(&expr, &mut Environment::new()).unwrap();
+        assert_eq!(out, 14);
+        assert_eq!(
+            steps,
+            [("3 * 4".to_string(), 12), ("2 + 3 * 4".to_string(), 14)]
+        );
+    }
+
+    #[test]
+    fn strict_mode_refuses_empty_statements() {
+        let program = |source: &str, strict: bool| {
+            parse_program(&mut Parser::new(tokenize(source).unwrap()).strict(strict))
+        };
+        for source in ["1;;2", "1 + 1;", ";1"] {
+            assert!(program(source, false).is_ok(), "{source}");
+            let message = program(source, true).unwrap_err().to_string();
+            assert!(message.contains("empty statement"), "{source}: {message}");
+        }
+        assert_eq!(program("1; 2", true).unwrap().len(), 2);
+        assert!(
+            error_message("1;;2").contains("Unprocessed"),
+            "parse still wants a single expression"
+        );
+    }
+
+    #[test]
+    fn merging_under_each_conflict_policy() {
+        let pair = || {
+            let mut mine = Environment::new();
+            eval_str("a = 1", &mut mine).unwrap();
+            eval_str("b = 2", &mut mine).unwrap();
+            let mut theirs = Environment::new();
+            eval_str("b = 20", &mut theirs).unwrap();
+            eval_str("c = 1 + 2", &mut theirs).unwrap();
+            (mine, theirs)
+        };
+        let (mut mine, theirs) = pair();
+        let overwritten = mine.merge(theirs, MergePolicy::Overwrite).unwrap();
+        assert_eq!(overwritten, ["b"]);
+        assert_eq!(mine.lookup("b").unwrap(), 20);
+        assert_eq!(mine.lookup("c").unwrap(), 3);
+        assert_eq!(mine.source("c"), Some("1 + 2"));
+
+        let (mut mine, theirs) = pair();
+        assert!(mine.merge(theirs, MergePolicy::Keep).unwrap().is_empty());
+        assert_eq!(mine.lookup("b").unwrap(), 2);
+        assert_eq!(mine.lookup("c").unwrap(), 3);
+        assert_eq!(mine.lookup("a").unwrap(), 1);
+    }
+
+    #[test]
+    fn bit_strings_of_positive_and_negative_values() {
+        assert_eq!(format_bits(10), format!("0b{}1010", "0".repeat(28)));
+        assert_eq!(format_bits(-1), format!("0b{}", "1".repeat(32)));
+        assert_eq!(format_bits(-2), format!("0b{}0", "1".repeat(31)));
+        assert_eq!(format_bits(i32::MIN), format!("0b1{}", "0".repeat(31)));
+    }
+
+    #[test]
+    fn a_name_is_a_call_only_before_a_parenthesis() {
+        let call = |args: Vec<Expr>| Expr::Call {
+            name: "f".to_string(),
+            args,
+        };
+        assert_eq!(
+            parse_str("f(2)").unwrap(),
+            call(vec![Expr::Number { n: 2 }])
+        );
+        assert_eq!(
+            parse_str("f (2)").unwrap(),
+            call(vec![Expr::Number { n: 2 }])
+        );
+        assert_eq!(parse_str("f()").unwrap(), call(vec![]));
+        assert_eq!(
+            parse_str("f").unwrap(),
+            Expr::Variable {
+                name: "f".to_string()
+            }
+        );
+        assert!(error_message("f [2]").contains("Unprocessed"));
+    }
+
+    #[test]
+    fn an_assumption_lasts_until_dropped() {
+        let mut env = Environment::new();
+        env.assign("x", 1).unwrap();
+        env.assume("x", 10).unwrap();
+        env.assume("y", 5).unwrap();
+        assert_eq!(eval_str("x + y", &mut env).unwrap(), 15);
+        env.drop_assumptions();
+        assert_eq!(env.lookup("x").unwrap(), 1);
+        assert!(!env.contains("y"));
+
+        // Assigning the name ends its override early.
+        env.assume("x", 10).unwrap();
+        eval_str("x = 2", &mut env).unwrap();
+        assert_eq!(env.lookup("x").unwrap(), 2);
+        env.drop_assumptions();
+        assert_eq!(env.lookup("x").unwrap(), 2);
+
+        env.max_value = 100;
+        assert!(env.assume("x", 101).is_err());
+    }
+
+    #[test]
+    fn each_comment_form_is_skipped() {
+        assert_eq!(eval("2 + 3 # note").unwrap(), 5);
+        assert_eq!(eval("2 + 3 // note").unwrap(), 5);
+        assert_eq!(eval("2 /* inline */ * 3").unwrap(), 6);
+        // A block comment may span lines and the whole input.
+        let tokens = tokenize("/* the\nwhole\ninput */").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].token_type, TokenType::Eof);
+        assert_eq!(eval("/**/ 4 / 2").unwrap(), 2);
+        let message = error_message("1 + /* open");
+        assert!(
+            message.contains("unterminated /* comment starting at column 5"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn read_only_evaluation_refuses_assignment() {
+        let mut env = Environment::new();
+        env.assign("x", 4).unwrap();
+        env.define_alias("double", parse_str("x * 2").unwrap());
+        assert_eq!(
+            evaluate_readonly(&parse_str("x + double").unwrap(), &env).unwrap(),
+            12
+        );
+        let message = evaluate_readonly(&parse_str("1 + (y = 2)").unwrap(), &env)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("can't assign y in a read-only evaluation"),
+            "{message}"
+        );
+        assert!(!env.contains("y"));
+    }
+
+    #[test]
+    fn suggestions_pick_the_nearest_name() {
+        let mut env = Environment::new();
+        env.assign("width", 3).unwrap();
+        env.assign("height", 4).unwrap();
+        let hint = |source: &str, env: &mut Environment| {
+            suggest_fix(&eval_str(source, env).unwrap_err(), env)
+        };
+        assert_eq!(
+            hint("widht * 2", &mut env).as_deref(),
+            Some("did you mean `width`?")
+        );
+        assert_eq!(
+            hint("mux(1, 2)", &mut env).as_deref(),
+            Some("did you mean `max`?")
+        );
+        assert_eq!(hint("depth", &mut env), None);
+        assert_eq!(hint("1 / 0", &mut env), None);
+        assert_eq!(closest_name("x", ["y"]), None);
+        assert_eq!(closest_name("ab", ["ac", "bb"]), Some("ac"));
+    }
+
+    #[test]
+    fn skipped_operands_of_a_comparison_chain_are_counted() {
+        let counted = |source: &str| {
+            evaluate_counted(&parse_str(source).unwrap(), &mut Environment::new()).unwrap()
+        };
+        assert_eq!(counted("1 > 2 > 1/0"), (0, 1));
+        assert_eq!(counted("1 < 0 < 1/0 < 1/0"), (0, 2));
+        assert_eq!(counted("1 < 2 < 3"), (1, 0));
+        assert_eq!(counted("(1 > 2 > 1/0) + (3 > 4 > 5)"), (0, 2));
+    }
+
+    #[test]
+    fn counting_skipped_operands_leaves_the_environment_alone() {
+        let mut env = Environment::new();
+        env.capture_printed();
+        eval_str("y = 1", &mut env).unwrap();
+        let expr = parse_str("(y = 5) > print(9) > 1/0").unwrap();
+        assert_eq!(count_skipped(&expr, &env).unwrap(), 1);
+        assert_eq!(env.lookup("y").unwrap(), 1);
+        assert!(env.take_printed().is_empty());
+    }
+
+    #[test]
+    fn names_may_hold_digits_and_underscores_after_a_letter() {
+        let tokens = tokenize("x_1 + nth_root(y2, 3)").unwrap();
+        let names: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Name)
+            .map(|t| &*t.val)
+            .collect();
+        assert_eq!(names, ["x_1", "nth_root", "y2"]);
+        // A leading digit still starts a number.
+        let tokens = tokenize("2x").unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::Num);
+        assert_eq!(tokens[1].val, "x");
+        assert!(tokenize("_x").is_err());
+        assert!(is_name("a_b2") && !is_name("2a") && !is_name("_a") && !is_name(""));
+        let mut env = Environment::new();
+        eval_str("rate_2 = 3", &mut env).unwrap();
+        assert_eq!(eval_str("rate_2 * 2", &mut env).unwrap(), 6);
+    }
+
+    #[test]
+    fn si_notation_at_several_magnitudes() {
+        assert_eq!(format_si(0), "0");
+        assert_eq!(format_si(7), "7");
+        assert_eq!(format_si(-999), "-999");
+        assert_eq!(format_si(1000), "1k");
+        assert_eq!(format_si(1500), "1.5k");
+        assert_eq!(format_si(12345), "12.3k");
+        assert_eq!(format_si(999_999), "1M");
+        assert_eq!(format_si(-2_345_678), "-2.35M");
+        assert_eq!(format_si(1_000_000_000), "1G");
+        assert_eq!(format_si(i32::MAX), "2.15G");
+        assert_eq!(format_si(i32::MIN), "-2.15G");
+    }
+
+    #[test]
+    fn validate_reports_every_broken_statement() {
+        let messages: Vec<String> = validate("1 +; x = 2; (3 * 4; 5")
+            .iter()
+            .map(Error::to_string)
+            .collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(
+            messages[0].contains("in the statement at column 1"),
+            "{}",
+            messages[0]
+        );
+        assert!(
+            messages[1].contains("in the statement at column 13"),
+            "{}",
+            messages[1]
+        );
+
+        let messages = validate("1 @ 2; 3 +");
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages[0].to_string().contains('@'), "{}", messages[0]);
+        assert!(validate("1; 2 + x;; 3").is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::error::{Error, Result};
+use crate::{check_input_len, is_name, is_name_char};
+use std::collections::HashMap;
+
+/// A text-substitution macro from `parse_macro`. A use of it is replaced
+/// by `body`, with each parameter name in `body` replaced by the text of
+/// the matching argument. Nothing is evaluated or parenthesised, so as in
+/// C, `sq(x) x*x` turns `sq(1+2)` into `1+2*1+2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    /// `None` for a macro defined without a parameter list, which is used
+    /// as a bare name; `Some` for one that is called with `(...)`.
+    pub params: Option<Vec<String>>,
+    pub body: String,
+}
+
+/// Reads a definition such as `sq(x) x*x`, or `two 1+1` for a macro used
+/// without arguments.
+pub fn parse_macro(definition: &str) -> Result<(String, Macro)> {
+    let definition = definition.trim();
+    let name_len = definition
+        .find(|c: char| !is_name_char(c))
+        .unwrap_or(definition.len());
+    let (name, rest) = definition.split_at(name_len);
+    if !is_name(name) {
+        return Err(Error::SyntaxError(
+            "expected :macro name(params) body".to_string(),
+        ));
+    }
+    let (params, body) = match rest.strip_prefix('(') {
+        Some(rest) => {
+            let Some((params, body)) = rest.split_once(')') else {
+                return Err(Error::SyntaxError(format!(
+                    "parameter list of macro {name} is not closed"
+                )));
+            };
+            let params: Vec<String> = match params.trim() {
+                "" => Vec::new(),
+                params => params.split(',').map(|p| p.trim().to_string()).collect(),
+            };
+            for (i, param) in params.iter().enumerate() {
+                if !is_name(param) {
+                    return Err(Error::SyntaxError(format!(
+                        "{param:?} isn't a valid parameter name"
+                    )));
+                }
+                if params[..i].contains(param) {
+                    return Err(Error::SyntaxError(format!(
+                        "parameter {param} appears twice in macro {name}"
+                    )));
+                }
+            }
+            (Some(params), body)
+        }
+        None => (None, rest),
+    };
+    let body = body.trim();
+    if body.is_empty() {
+        return Err(Error::SyntaxError(format!("macro {name} has no body")));
+    }
+    let mac = Macro {
+        params,
+        body: body.to_string(),
+    };
+    Ok((name.to_string(), mac))
+}
+
+/// Replaces every macro use in `source`, before it is tokenized.
+/// Arguments are expanded first, then the substituted body is expanded
+/// again, so macros may use other macros; one that reaches itself, directly
+/// or through others, is an error rather than an endless expansion.
+///
+/// A short line can expand into a huge one, so expansion stops with
+/// `Error::InputTooLong` as soon as its output passes `max_len` bytes, the
+/// same limit `check_input_len` puts on what was typed. The length in the
+/// error is how far expansion had got.
+pub fn expand_macros(
+    source: &str,
+    macros: &HashMap<String, Macro>,
+    max_len: usize,
+) -> Result<String> {
+    expand(source, macros, &mut Vec::new(), max_len)
+}
+
+/// `expanding` holds the macros whose bodies are being expanded. Every
+/// piece pushed onto the output is at most `max_len` long, so checking
+/// before each one keeps the whole within about twice that.
+fn expand(
+    source: &str,
+    macros: &HashMap<String, Macro>,
+    expanding: &mut Vec<String>,
+    max_len: usize,
+) -> Result<String> {
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic()) {
+        check_input_len(&out, max_len)?;
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        let (name, after) = rest.split_at(len);
+        let Some(mac) = macros.get(name) else {
+            out.push_str(name);
+            rest = after;
+            continue;
+        };
+        if expanding.iter().any(|active| active == name) {
+            return Err(Error::SyntaxError(format!(
+                "macro {name} expands to itself: {} -> {name}",
+                expanding.join(" -> ")
+            )));
+        }
+        let Some(params) = &mac.params else {
+            expanding.push(name.to_string());
+            let expanded = expand(&mac.body, macros, expanding, max_len);
+            expanding.pop();
+            out.push_str(&expanded?);
+            rest = after;
+            continue;
+        };
+        let args = if let Some(inner) = after.trim_start().strip_prefix('(') {
+            let (args, after_args) = split_args(name, inner)?;
+            rest = after_args;
+            args
+        } else {
+            // Without arguments the name is left alone, as in C.
+            out.push_str(name);
+            rest = after;
+            continue;
+        };
+        if args.len() != params.len() {
+            return Err(Error::SyntaxError(format!(
+                "macro {name} takes {} argument(s), got {}",
+                params.len(),
+                args.len()
+            )));
+        }
+        let args = args
+            .into_iter()
+            .map(|arg| expand(arg, macros, expanding, max_len))
+            .collect::<Result<Vec<String>>>()?;
+        let body = substitute(&mac.body, params, &args, max_len)?;
+        expanding.push(name.to_string());
+        let expanded = expand(&body, macros, expanding, max_len);
+        expanding.pop();
+        out.push_str(&expanded?);
+    }
+    out.push_str(rest);
+    check_input_len(&out, max_len)?;
+    Ok(out)
+}
+
+/// Splits the arguments after a macro's `(` at top-level commas,
+/// returning them with the text after the closing `)`.
+fn split_args<'a>(name: &str, inner: &'a str) -> Result<(Vec<&'a str>, &'a str)> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut arg_start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' if depth == 0 => {
+                args.push(&inner[arg_start..i]);
+                let args = match args.as_slice() {
+                    [only] if only.trim().is_empty() => Vec::new(),
+                    _ => args,
+                };
+                return Ok((args, &inner[i + 1..]));
+            }
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(&inner[arg_start..i]);
+                arg_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    Err(Error::SyntaxError(format!(
+        "arguments to macro {name} are not closed by a )"
+    )))
+}
+
+/// `body` with each whole-word use of a parameter replaced by its argument,
+/// or `Error::InputTooLong` once that passes `max_len` bytes.
+fn substitute(body: &str, params: &[String], args: &[String], max_len: usize) -> Result<String> {
+    let mut out = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic()) {
+        check_input_len(&out, max_len)?;
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        let (word, after) = rest.split_at(len);
+        match params.iter().position(|param| param == word) {
+            Some(i) => out.push_str(args[i].trim()),
+            None => out.push_str(word),
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    check_input_len(&out, max_len)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn macros(definitions: &[&str]) -> HashMap<String, Macro> {
+        definitions
+            .iter()
+            .map(|definition| parse_macro(definition).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn expansion_substitutes_arguments_textually() {
+        let defs = macros(&["sq(x) x*x", "two 1+1", "quad(x) sq(sq(x))"]);
+        assert_eq!(expand_macros("sq(5)", &defs, 100).unwrap(), "5*5");
+        assert_eq!(expand_macros("sq(1+2)", &defs, 100).unwrap(), "1+2*1+2");
+        assert_eq!(
+            expand_macros("two * sq(two)", &defs, 100).unwrap(),
+            "1+1 * 1+1*1+1"
+        );
+        assert_eq!(expand_macros("quad(3)", &defs, 100).unwrap(), "3*3*3*3");
+        // Without arguments a macro that takes them is left alone.
+        assert_eq!(expand_macros("sq + 1", &defs, 100).unwrap(), "sq + 1");
+    }
+
+    #[test]
+    fn a_macro_reaching_itself_is_an_error() {
+        let defs = macros(&["loop(x) x + again(x)", "again(x) loop(x)"]);
+        let message = expand_macros("loop(1)", &defs, 100)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("macro loop expands to itself: loop -> again -> loop"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn expansion_stops_once_past_the_length_limit() {
+        // Each macro doubles the one after it: 2^20 copies of `x` in all.
+        let letters: Vec<char> = ('a'..='u').collect();
+        let mut definitions: Vec<String> = letters
+            .windows(2)
+            .map(|pair| format!("m{0} m{1} m{1}", pair[0], pair[1]))
+            .collect();
+        definitions.push("mu x".to_string());
+        let defs = macros(&definitions.iter().map(String::as_str).collect::<Vec<_>>());
+        assert!(matches!(
+            expand_macros("ma", &defs, 1000),
+            Err(Error::InputTooLong { max: 1000, .. })
+        ));
+        assert_eq!(expand_macros("mr", &defs, 1000).unwrap().len(), 15);
+
+        let defs = macros(&["many(x) x x x x x x x x"]);
+        assert!(matches!(
+            expand_macros("many(many(many(many(123))))", &defs, 1000),
+            Err(Error::InputTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn macro_names_follow_the_identifier_rules() {
+        let defs = macros(&["sq_2(x_1) x_1*x_1 + 2"]);
+        assert_eq!(expand_macros("sq_2(a1)", &defs, 100).unwrap(), "a1*a1 + 2");
+        // Part of a longer name isn't a use of the macro.
+        assert_eq!(expand_macros("sq_22(1)", &defs, 100).unwrap(), "sq_22(1)");
+        assert!(parse_macro("2sq(x) x").is_err());
+        assert!(parse_macro("sq(_x) 1").is_err());
+    }
+}
@@ -1,257 +1,937 @@
-mod error;
-use crate::error::{Error, Result};
-use std::collections::HashMap;
-use std::fmt;
+use rust_calc::builtins::{BUILTINS, arity, describe_arity};
+use rust_calc::error::Error;
+use rust_calc::macros::{Macro, expand_macros, parse_macro};
+use rust_calc::rational::{Rational, evaluate_exact_all, format_rationals};
+use rust_calc::{
+    DEFAULT_MAX_INPUT_BYTES, DivisionMode, Environment, Expr, Parser, PhaseTimes, Stats, Token,
+    TokenType, check_input_len, count_skipped, eval_profiled, eval_str, eval_timed, evaluate,
+    evaluate_all, evaluate_traced, explain, expr_to_dot, first_difference, format_bits,
+    format_result, format_si, format_tokens, format_values, is_name, parse, parse_program,
+    result_to_json, suggest_fix, token_type_counts, tokenize, tokenize_with_comments,
+    tokens_to_json, unparse,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
 use std::io;
-use std::io::Write;
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum TokenType {
-    Num,
-    Name,
-    Plus,
-    Minus,
-    Times,
-    Lparen,
-    Rparen,
-    Assign,
-}
-
-#[derive(Debug, Clone)]
-struct Token {
-    token_type: TokenType,
-    val: String,
-}
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-fn tokenize(source: &str) -> Result<Vec<Token>> {
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut n: usize = 0;
-    let source_chars: Vec<char> = source.chars().collect();
-    while n < source_chars.len() {
-        if source_chars[n].is_whitespace() {
-            n += 1;
+use std::io::{IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+
+const HELP: &str = "\
+:help            show this message
+:reload-config   re-read the settings in ~/.tiny_calc.toml
+:group on|off    group result digits with thousands separators
+:notation <mode> si shows results with suffixes such as 1.5k, plain in full
+:time <expr>     evaluate <expr> and show how long it took
+:profile <expr>  evaluate <expr>, timing each phase separately
+:tape <expr>     evaluate <expr>, showing the value of each step
+:parse <expr>    show the tree <expr> parses to, without evaluating it
+:format <expr>   reprint <expr> spaced evenly, with only the parentheses it needs
+:explain <expr>  describe how <expr> is parsed, in words
+:stats <expr>    summarise <expr>, then evaluate it, counting skipped operands
+:diff <a> | <b>  compare how <a> and <b> parse
+:bits <expr>     show the result of <expr> in binary, as 32 bits
+:describe <name> show what <name> holds and what last assigned it
+:funcs           list the built-in functions
+:env             print the variables as statements that recreate them
+:alias n = expr  make n stand for expr, re-evaluated each time n is used
+:assume n = expr give n the value of expr for the next evaluation only
+:macro f(x) body expand f(...) to body in later lines; alone, list macros
+:watch <name>    print <name> each time it is assigned; alone, list watches
+:unwatch <name>  stop printing <name> when it is assigned";
+
+/// One line per built-in, alphabetically, with the arguments it takes
+/// lined up past the longest name.
+fn format_funcs(builtins: &[(&str, usize, usize)]) -> String {
+    let mut builtins = builtins.to_vec();
+    builtins.sort_by_key(|&(name, _, _)| name);
+    let width = builtins
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<String> = builtins
+        .iter()
+        .map(|&(name, min, max)| format!("{name:<width$} {} argument(s)", describe_arity(min, max)))
+        .collect();
+    lines.join("\n")
+}
+
+/// One `:macro` definition per line, alphabetically, as it was entered.
+fn format_macros(macros: &HashMap<String, Macro>) -> String {
+    if macros.is_empty() {
+        return "no macros".to_string();
+    }
+    let mut lines: Vec<String> = macros
+        .iter()
+        .map(|(name, mac)| match &mac.params {
+            None => format!("{name} {}", mac.body),
+            Some(params) => format!("{name}({}) {}", params.join(", "), mac.body),
+        })
+        .collect();
+    lines.sort();
+    lines.join("\n")
+}
+
+/// The environment as `name = value;` statements, one per line, which
+/// evaluate back to the same variables.
+fn format_env(env: &Environment) -> String {
+    let lines: Vec<String> = env
+        .vars()
+        .into_iter()
+        .map(|(name, n)| {
+            let assign = Expr::Assign {
+                location: Box::new(Expr::Variable { name }),
+                value: Box::new(Expr::Number { n }),
+            };
+            format!("{};", unparse(&assign))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// A small table of per-phase timings, in microseconds.
+fn format_phase_times(times: &PhaseTimes) -> String {
+    let phases = [
+        ("tokenize", times.tokenize),
+        ("parse", times.parse),
+        ("evaluate", times.evaluate),
+    ];
+    let lines: Vec<String> = phases
+        .iter()
+        .map(|(phase, elapsed)| format!("{phase:<8} {:>6} µs", elapsed.as_micros()))
+        .collect();
+    lines.join("\n")
+}
+
+/// The summary from `expr_stats`, then one line per token type in
+/// `tokens`, most frequent first, leaving out the closing `Eof`.
+fn format_stats(stats: &Stats, tokens: &[Token]) -> String {
+    let mut counts: Vec<(TokenType, usize)> = token_type_counts(tokens)
+        .into_iter()
+        .filter(|&(token_type, _)| token_type != TokenType::Eof)
+        .collect();
+    counts.sort_by_key(|&(token_type, count)| (Reverse(count), format!("{token_type:?}")));
+    let mut lines = vec![
+        format!("tokens     {}", stats.tokens),
+        format!("operators  {}", stats.operators),
+        format!("operands   {}", stats.operands),
+        format!("depth      {}", stats.paren_depth),
+        format!("nodes      {}", stats.nodes),
+        "by type:".to_string(),
+    ];
+    lines.extend(
+        counts
+            .iter()
+            .map(|(token_type, count)| format!("  {:<10} {count}", format!("{token_type:?}"))),
+    );
+    lines.join("\n")
+}
+
+/// Parses both sides of `a | b` and says whether the trees match, or else
+/// where they first part ways.
+fn format_diff(sides: &str, implicit_mul: bool) -> Result<String, Error> {
+    let Some((a, b)) = sides.split_once('|') else {
+        return Err(Error::SyntaxError("expected :diff expr | expr".to_string()));
+    };
+    let a = parse_line(a, implicit_mul)?;
+    let b = parse_line(b, implicit_mul)?;
+    Ok(match first_difference(&a, &b) {
+        None => "same tree".to_string(),
+        Some((x, y)) => format!("differ at `{}` vs `{}`", unparse(x), unparse(y)),
+    })
+}
+
+/// Parses one expression given to a REPL command, honouring
+/// `--implicit-mul`. Nothing is evaluated, so names needn't be defined.
+fn parse_line(source: &str, implicit_mul: bool) -> Result<Expr, Error> {
+    let tokens = tokenize(source)?;
+    parse(&mut Parser::new(tokens).implicit_mul(implicit_mul))
+}
+
+/// Like `parse_line`, also handing back the tokens it parsed.
+fn parse_line_with_tokens(source: &str, implicit_mul: bool) -> Result<(Vec<Token>, Expr), Error> {
+    let tokens = tokenize(source)?;
+    let expr = parse(&mut Parser::new(tokens.clone()).implicit_mul(implicit_mul))?;
+    Ok((tokens, expr))
+}
+
+/// The tree `source` parses to, pretty-printed.
+fn format_parse(source: &str, implicit_mul: bool) -> Result<String, Error> {
+    Ok(format!("{:#?}", parse_line(source, implicit_mul)?))
+}
+
+/// What `name` refers to: a variable's value, with the expression that
+/// last assigned it when known, an alias's definition, or a built-in.
+fn format_describe(name: &str, env: &Environment) -> String {
+    if env.contains(name) {
+        let val = env.lookup_or(name, 0);
+        return match env.source(name) {
+            Some(source) => format!("{name} = {val}, last assigned from {source}"),
+            None => format!("{name} = {val}"),
+        };
+    }
+    if let Some(expr) = env.alias(name) {
+        return format!("{name} is an alias for {}", unparse(expr));
+    }
+    match arity(name) {
+        Some((min, max)) => format!(
+            "{name} is a built-in function taking {} argument(s)",
+            describe_arity(min, max)
+        ),
+        None => format!("{name} is not defined"),
+    }
+}
+
+/// Splits `name = expr` for `:alias` and `:assume`, parsing the
+/// expression unevaluated.
+fn parse_definition<'a>(command: &str, definition: &'a str) -> Result<(&'a str, Expr), Error> {
+    let Some((name, source)) = definition.split_once('=') else {
+        return Err(Error::SyntaxError(format!(
+            "expected {command} name = expr"
+        )));
+    };
+    let name = name.trim();
+    if !is_name(name) {
+        return Err(Error::SyntaxError(format!(
+            "{name:?} isn't a valid variable name"
+        )));
+    }
+    let expr = parse(&mut Parser::new(tokenize(source)?))?;
+    Ok((name, expr))
+}
+
+fn banner() -> String {
+    format!(
+        "tiny calc {} - type :help for commands, an empty line to quit",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Where the REPL's default settings live: `~/.tiny_calc.toml`.
+fn config_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".tiny_calc.toml"))
+}
+
+/// Applies the settings file, if there is one, on top of `config`.
+/// Problems in it are reported and the offending lines skipped.
+fn load_config(config: &mut ReplConfig) {
+    let Some(path) = config_path() else {
+        return;
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {e}", path.display());
+            return;
+        }
+    };
+    for problem in apply_config(&text, config) {
+        eprintln!("{}: {problem}", path.display());
+    }
+}
+
+/// Reads `key = value` lines, a small subset of TOML, into `config`. The
+/// keys are `group`, `color`, `max_input_bytes` and `implicit_mul`; blank
+/// lines and `#` comments are skipped. Returns a message for each line that
+/// couldn't be used, leaving that setting as it was. Settings given on the
+/// command line are put back afterwards, so the file never undoes them.
+fn apply_config(text: &str, config: &mut ReplConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
             continue;
-        } else if source_chars[n].is_ascii_digit() {
-            let start = n;
-            while n < source_chars.len() && source_chars[n].is_ascii_digit() {
-                n += 1;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            problems.push(format!("line {}: expected key = value", i + 1));
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim().trim_matches('"'));
+        let applied = match key {
+            "group" => value.parse().map(|group| config.group = group).is_ok(),
+            "implicit_mul" => value.parse().map(|on| config.implicit_mul = on).is_ok(),
+            "max_input_bytes" => value
+                .parse()
+                .map(|max| config.max_input_bytes = max)
+                .is_ok(),
+            "color" => {
+                let mode = match value {
+                    "auto" => Some(ColorMode::Auto),
+                    "always" => Some(ColorMode::Always),
+                    "never" => Some(ColorMode::Never),
+                    _ => None,
+                };
+                mode.map(|mode| config.color = mode.enabled(io::stdout().is_terminal()))
+                    .is_some()
             }
-            tokens.push(Token {
-                token_type: TokenType::Num,
-                val: source_chars[start..n].iter().collect(),
-            });
-        } else if source_chars[n].is_ascii_alphabetic() {
-            let start = n;
-            while n < source_chars.len() && source_chars[n].is_ascii_alphabetic() {
-                n += 1;
+            _ => {
+                problems.push(format!("line {}: unknown setting `{key}`", i + 1));
+                continue;
             }
-            tokens.push(Token {
-                token_type: TokenType::Name,
-                val: source_chars[start..n].iter().collect(),
-            });
-        } else {
-            let token = match source_chars[n] {
-                '+' => Ok(Token {
-                    token_type: TokenType::Plus,
-                    val: String::from('+'),
-                }),
-                '*' => Ok(Token {
-                    token_type: TokenType::Times,
-                    val: String::from('*'),
-                }),
-
-                '-' => Ok(Token {
-                    token_type: TokenType::Minus,
-                    val: String::from('-'),
-                }),
-                '(' => Ok(Token {
-                    token_type: TokenType::Lparen,
-                    val: String::from('('),
-                }),
-                ')' => Ok(Token {
-                    token_type: TokenType::Rparen,
-                    val: String::from(')'),
-                }),
-                '=' => Ok(Token {
-                    token_type: TokenType::Assign,
-                    val: String::from('='),
-                }),
-                _ => Err(Error::SyntaxError(
-                    format!("Couldn't parse {} to a token", source_chars[n]).to_string(),
-                )),
-            };
-            tokens.push(token?);
-            n += 1;
+        };
+        if !applied {
+            problems.push(format!("line {}: invalid value `{value}` for {key}", i + 1));
         }
     }
+    config.apply_overrides();
+    problems
+}
 
-    Ok(tokens)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    /// One `result_to_json` object per line, on stdout even for errors.
+    Json,
 }
 
-#[derive(Debug)]
-enum Expr {
-    Number {
-        n: i32,
-    },
-    Variable {
-        name: String,
-    },
-    Assign {
-        location: Box<Expr>,
-        value: Box<Expr>,
-    },
-    Add {
-        left: Box<Expr>,
-        right: Box<Expr>,
-    },
-
-    Minus {
-        left: Box<Expr>,
-        right: Box<Expr>,
-    },
-
-    Mul {
-        left: Box<Expr>,
-        right: Box<Expr>,
-    },
-}
-impl fmt::Display for Expr {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorMode {
+    Always,
+    Never,
+    Auto,
+}
+
+impl ColorMode {
+    /// Whether to emit color. `Auto` only colors interactive terminal output.
+    fn enabled(self, interactive: bool) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => interactive,
+        }
+    }
+}
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+
+/// Wraps `text` in the ANSI escape for `color` when `enabled`.
+fn paint(text: &str, color: &str, enabled: bool) -> String {
+    if enabled {
+        format!("\x1b[{color}m{text}\x1b[0m")
+    } else {
+        text.to_string()
     }
 }
 
 #[derive(Debug)]
-struct Parser {
-    tokens: Vec<Token>,
-    n: usize,
+struct ReplConfig {
+    group: bool,
+    /// `:notation si`: show results as `1.5k` and so on.
+    si: bool,
+    color: bool,
+    max_input_bytes: usize,
+    implicit_mul: bool,
+    exact: bool,
+    explain_errors: bool,
+    /// Macros from `:macro`, expanded in each line before it's tokenized.
+    macros: HashMap<String, Macro>,
+    /// Variables `:watch` echoes on assignment. Shared with the
+    /// environment's `on_assign` hook, which `repl` installs.
+    watched: Rc<RefCell<BTreeSet<String>>>,
+    overrides: Overrides,
+}
+
+/// REPL settings given on the command line. They take precedence over the
+/// settings file, including when `:reload-config` reads it again.
+#[derive(Debug, Default, Clone, Copy)]
+struct Overrides {
+    color: Option<bool>,
+    max_input_bytes: Option<usize>,
+    implicit_mul: bool,
 }
 
-impl Parser {
-    fn accept(&mut self, token_type: TokenType) -> bool {
-        if self.n < self.tokens.len() && self.tokens[self.n].token_type == token_type {
-            self.n += 1;
-            return true;
+impl Default for ReplConfig {
+    fn default() -> Self {
+        Self {
+            group: false,
+            si: false,
+            color: false,
+            max_input_bytes: DEFAULT_MAX_INPUT_BYTES,
+            implicit_mul: false,
+            exact: false,
+            explain_errors: false,
+            macros: HashMap::new(),
+            watched: Rc::default(),
+            overrides: Overrides::default(),
         }
-        false
     }
-    fn last(&self) -> Result<Token> {
-        if self.n >= self.tokens.len() {
-            return Err(Error::SyntaxError("Syntax error somewhere.".to_string()));
+}
+
+impl ReplConfig {
+    fn apply_overrides(&mut self) {
+        if let Some(color) = self.overrides.color {
+            self.color = color;
+        }
+        if let Some(max) = self.overrides.max_input_bytes {
+            self.max_input_bytes = max;
+        }
+        self.implicit_mul |= self.overrides.implicit_mul;
+    }
+    /// One value, with SI suffixes under `:notation si` and otherwise as
+    /// `format_result` renders it.
+    fn format(&self, val: i32) -> String {
+        if self.si {
+            format_si(val)
+        } else {
+            format_result(val, self.group)
         }
-        Ok(self.tokens[self.n - 1].clone())
     }
-    fn at_end(&self) -> bool {
-        self.n <= self.tokens.len()
+    fn print_result(&self, out: i32) {
+        println!("{}", paint(&self.format(out), GREEN, self.color));
+    }
+    fn print_values(&self, out: &[i32]) {
+        let text = match out {
+            [val] => self.format(*val),
+            _ if !self.si => format_values(out, self.group),
+            _ => {
+                let vals: Vec<String> = out.iter().map(|&val| self.format(val)).collect();
+                format!("[{}]", vals.join(", "))
+            }
+        };
+        println!("{}", paint(&text, GREEN, self.color));
+    }
+    fn print_error(&self, e: &Error) {
+        println!("{}", paint(&format!("{e:?}"), RED, self.color));
+    }
+    /// `print_error` plus, with `--explain-errors`, a hint from `env`.
+    fn print_eval_error(&self, e: &Error, env: &Environment) {
+        self.print_error(e);
+        if let Some(hint) = self.explain_errors.then(|| suggest_fix(e, env)).flatten() {
+            println!("hint: {hint}");
+        }
     }
 }
 
-fn parse_term(p: &mut Parser) -> Result<Expr> {
-    if p.accept(TokenType::Num) {
-        Ok(Expr::Number {
-            n: p.last()?.val.parse().expect("couldn't parse digit"),
-        })
-    } else if p.accept(TokenType::Name) {
-        Ok(Expr::Variable {
-            name: p.last()?.val,
-        })
-    } else if p.accept(TokenType::Lparen) {
-        let e = parse_expression(p)?;
-        if !p.accept(TokenType::Rparen) {
-            Err(Error::SyntaxError(format!(
-                "( not closed by a ). Found ( {e} "
-            )))
-        } else {
-            Ok(e)
+fn run_command(command: &str, config: &mut ReplConfig, env: &mut Environment) {
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    match (name, arg) {
+        (":help", "") => println!("{HELP}"),
+        (":funcs", "") => println!("{}", format_funcs(BUILTINS)),
+        (":env", "") => println!("{}", format_env(env)),
+        (":alias", definition) => match parse_definition(name, definition) {
+            Ok((name, expr)) => env.define_alias(name, expr),
+            Err(e) => config.print_error(&e),
+        },
+        (":assume", definition) => {
+            let assumed = parse_definition(name, definition).and_then(|(name, expr)| {
+                let val = evaluate(&expr, env)?;
+                env.assume(name, val)
+            });
+            if let Err(e) = assumed {
+                config.print_error(&e);
+            }
         }
-    } else {
-        Err(Error::SyntaxError("Cannot process token".to_string()))
+        (":macro", "") => println!("{}", format_macros(&config.macros)),
+        (":macro", definition) => match parse_macro(definition) {
+            Ok((name, mac)) => {
+                config.macros.insert(name, mac);
+            }
+            Err(e) => config.print_error(&e),
+        },
+        (":watch", "") => {
+            let watched = config.watched.borrow();
+            if watched.is_empty() {
+                println!("nothing watched");
+            } else {
+                let names: Vec<&str> = watched.iter().map(String::as_str).collect();
+                println!("{}", names.join(", "));
+            }
+        }
+        (":watch", names) => {
+            let mut watched = config.watched.borrow_mut();
+            watched.extend(names.split_whitespace().map(String::from));
+        }
+        (":unwatch", names) if !names.is_empty() => {
+            for name in names.split_whitespace() {
+                if !config.watched.borrow_mut().remove(name) {
+                    println!("{name} is not watched");
+                }
+            }
+        }
+        (":reload-config", "") => load_config(config),
+        (":group", "on") => config.group = true,
+        (":group", "off") => config.group = false,
+        (":notation", "si") => config.si = true,
+        (":notation", "plain") => config.si = false,
+        (":time", expr) if !expr.is_empty() => match eval_timed(expr, env) {
+            (Ok(out), elapsed) => {
+                env.drop_assumptions();
+                config.print_result(out);
+                println!("({} µs)", elapsed.as_micros());
+            }
+            (Err(e), _) => config.print_error(&e),
+        },
+        (":profile", expr) if !expr.is_empty() => {
+            let (out, times) = eval_profiled(expr, env);
+            match out {
+                Ok(out) => {
+                    env.drop_assumptions();
+                    config.print_result(out);
+                }
+                Err(e) => config.print_error(&e),
+            }
+            println!("{}", format_phase_times(&times));
+        }
+        (":stats", source) if !source.is_empty() => {
+            match parse_line_with_tokens(source, config.implicit_mul) {
+                Ok((tokens, expr)) => {
+                    println!("{}", format_stats(&Stats::of(&tokens, &expr), &tokens));
+                    match count_skipped(&expr, env) {
+                        Ok(skipped) => println!("skipped    {skipped}"),
+                        Err(e) => config.print_error(&e),
+                    }
+                }
+                Err(e) => config.print_error(&e),
+            }
+        }
+        (":diff", sides) => match format_diff(sides, config.implicit_mul) {
+            Ok(report) => println!("{report}"),
+            Err(e) => config.print_error(&e),
+        },
+        (":bits", expr) if !expr.is_empty() => match eval_str(expr, env) {
+            Ok(out) => {
+                env.drop_assumptions();
+                println!("{} ({out})", format_bits(out));
+            }
+            Err(e) => config.print_error(&e),
+        },
+        (":describe", name) if !name.is_empty() => println!("{}", format_describe(name, env)),
+        (":tape", source) if !source.is_empty() => {
+            let traced = parse_line(source, config.implicit_mul)
+                .and_then(|expr| evaluate_traced(&expr, env));
+            match traced {
+                Ok((_, steps)) => {
+                    env.drop_assumptions();
+                    for (step, val) in steps {
+                        println!("{step} = {}", config.format(val));
+                    }
+                }
+                Err(e) => config.print_error(&e),
+            }
+        }
+        (":parse", source) if !source.is_empty() => {
+            match format_parse(source, config.implicit_mul) {
+                Ok(tree) => println!("{tree}"),
+                Err(e) => config.print_error(&e),
+            }
+        }
+        (":format", source) if !source.is_empty() => {
+            match parse_line(source, config.implicit_mul) {
+                Ok(expr) => println!("{}", unparse(&expr)),
+                Err(e) => config.print_error(&e),
+            }
+        }
+        (":explain", source) if !source.is_empty() => {
+            match parse_line(source, config.implicit_mul) {
+                Ok(expr) => println!("{}", explain(&expr)),
+                Err(e) => config.print_error(&e),
+            }
+        }
+        _ => println!("Unknown command: {command}"),
     }
 }
 
-fn parse_expression(p: &mut Parser) -> Result<Expr> {
-    let left = Box::new(parse_term(p)?);
-    if p.accept(TokenType::Plus) {
-        Ok(Expr::Add {
-            left,
-            right: Box::new(parse_term(p)?),
-        })
-    } else if p.accept(TokenType::Minus) {
-        Ok(Expr::Minus {
-            left,
-            right: Box::new(parse_term(p)?),
-        })
-    } else if p.accept(TokenType::Times) {
-        Ok(Expr::Mul {
-            left,
-            right: Box::new(parse_term(p)?),
-        })
-    } else if p.accept(TokenType::Assign) {
-        Ok(Expr::Assign {
-            location: left,
-            value: Box::new(parse_expression(p)?),
-        })
-    } else {
-        Ok(*left)
+#[derive(Debug)]
+struct Options {
+    show_banner: bool,
+    json_tokens: bool,
+    ast_dot: bool,
+    silent: bool,
+    keep_going: bool,
+    implicit_mul: bool,
+    zero_default: bool,
+    strict: bool,
+    /// Evaluate with exact fractions instead of integer division.
+    exact: bool,
+    /// Follow an unknown-name error with a "did you mean" hint.
+    explain_errors: bool,
+    stdin: bool,
+    /// `None` unless given on the command line, so a config file's
+    /// setting can apply instead.
+    color: Option<ColorMode>,
+    max_input_bytes: Option<usize>,
+    output: OutputFormat,
+    division: DivisionMode,
+    min_value: i32,
+    max_value: i32,
+    /// Fixes the `rand` sequence; otherwise it's seeded from the clock.
+    seed: Option<u64>,
+    eval: Option<String>,
+    script: Option<String>,
+}
+
+impl Options {
+    fn parser(&self, tokens: Vec<Token>) -> Parser {
+        Parser::new(tokens)
+            .implicit_mul(self.implicit_mul)
+            .strict(self.strict)
+    }
+    fn environment(&self) -> Environment {
+        let mut env = Environment::new();
+        env.division = self.division;
+        env.min_value = self.min_value;
+        env.max_value = self.max_value;
+        env.zero_default = self.zero_default;
+        if let Some(seed) = self.seed {
+            env.seed(seed);
+        }
+        env
     }
 }
 
-fn parse(p: &mut Parser) -> Result<Expr> {
-    let e = parse_expression(p)?;
-    if !p.at_end() {
-        return Err(Error::SyntaxError(
-            format!(
-                "Unprocessed characters remain. Last unprocessed: {}",
-                p.last()?
-            )
-            .to_string(),
-        ));
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{msg}");
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        show_banner: true,
+        json_tokens: false,
+        ast_dot: false,
+        silent: false,
+        keep_going: false,
+        implicit_mul: false,
+        zero_default: false,
+        strict: false,
+        exact: false,
+        explain_errors: false,
+        stdin: !io::stdin().is_terminal(),
+        color: None,
+        max_input_bytes: None,
+        output: OutputFormat::Text,
+        division: DivisionMode::Truncated,
+        min_value: i32::MIN,
+        max_value: i32::MAX,
+        seed: None,
+        eval: None,
+        script: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-banner" => opts.show_banner = false,
+            "--json-tokens" => opts.json_tokens = true,
+            "--ast-dot" => opts.ast_dot = true,
+            "--stdin" => opts.stdin = true,
+            "--silent" => opts.silent = true,
+            "--keep-going" => opts.keep_going = true,
+            "--implicit-mul" => opts.implicit_mul = true,
+            "--zero-default" => opts.zero_default = true,
+            "--strict" => opts.strict = true,
+            "--precision-exact" => opts.exact = true,
+            "--explain-errors" => opts.explain_errors = true,
+            "--color" | "--color=auto" => opts.color = Some(ColorMode::Auto),
+            "--color=always" => opts.color = Some(ColorMode::Always),
+            "--color=never" => opts.color = Some(ColorMode::Never),
+            "--output=text" => opts.output = OutputFormat::Text,
+            "--output=json" => opts.output = OutputFormat::Json,
+            "--division=truncated" => opts.division = DivisionMode::Truncated,
+            "--division=floored" => opts.division = DivisionMode::Floored,
+            "--eval" => match args.next() {
+                Some(expr) => opts.eval = Some(expr),
+                None => usage_error("--eval needs an expression"),
+            },
+            _ if arg.starts_with("--min-value=") => match arg["--min-value=".len()..].parse() {
+                Ok(min) => opts.min_value = min,
+                Err(_) => usage_error(&format!("Invalid bound in {arg}")),
+            },
+            _ if arg.starts_with("--max-value=") => match arg["--max-value=".len()..].parse() {
+                Ok(max) => opts.max_value = max,
+                Err(_) => usage_error(&format!("Invalid bound in {arg}")),
+            },
+            _ if arg.starts_with("--seed=") => match arg["--seed=".len()..].parse() {
+                Ok(seed) => opts.seed = Some(seed),
+                Err(_) => usage_error(&format!("Invalid seed in {arg}")),
+            },
+            _ if arg.starts_with("--max-input-bytes=") => {
+                match arg["--max-input-bytes=".len()..].parse() {
+                    Ok(max) => opts.max_input_bytes = Some(max),
+                    Err(_) => usage_error(&format!("Invalid byte count in {arg}")),
+                }
+            }
+            _ if !arg.starts_with('-') && opts.script.is_none() => opts.script = Some(arg),
+            _ => usage_error(&format!("Unknown argument: {arg}")),
+        }
     }
-    Ok(e)
+    if opts.min_value > opts.max_value {
+        usage_error("--min-value can't be above --max-value");
+    }
+    opts
 }
 
-struct Environment {
-    vars: HashMap<String, i32>,
+/// The non-interactive input, taken from `--eval`, a script file, or a
+/// piped stdin, in that order.
+fn batch_input(opts: &Options) -> Option<String> {
+    if let Some(expr) = &opts.eval {
+        return Some(expr.clone());
+    }
+    let Some(path) = opts.script.as_ref() else {
+        if !opts.stdin {
+            return None;
+        }
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            eprintln!("Couldn't read stdin: {e}");
+            process::exit(1);
+        }
+        return Some(source);
+    };
+    match fs::read_to_string(path) {
+        Ok(source) => Some(source),
+        Err(e) => {
+            eprintln!("Couldn't read {path}: {e}");
+            process::exit(1);
+        }
+    }
 }
 
-impl Environment {
-    fn new() -> Self {
-        Self {
-            vars: HashMap::new(),
+/// Splits a script into statements, one per line, except that a line
+/// leaving a bracket or a `/*` comment open carries on into the next ones
+/// until it closes, so a long call can be spread over several lines.
+fn logical_lines(source: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0i32;
+    let mut in_block = false;
+    for line in source.lines() {
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('*', Some('/')) if in_block => {
+                    chars.next();
+                    in_block = false;
+                }
+                _ if in_block => {}
+                ('/', Some('*')) => {
+                    chars.next();
+                    in_block = true;
+                }
+                ('#', _) | ('/', Some('/')) => break,
+                ('(' | '[', _) => depth += 1,
+                (')' | ']', _) => depth -= 1,
+                _ => {}
+            }
+        }
+        if depth <= 0 && !in_block {
+            lines.push(std::mem::take(&mut current));
+            depth = 0;
         }
     }
-    fn assign(&mut self, name: &str, val: i32) {
-        self.vars.insert(name.to_string(), val);
+    if !current.is_empty() {
+        lines.push(current);
     }
-    fn lookup(&self, name: &str) -> i32 {
-        *self.vars.get(name).unwrap()
+    lines
+}
+
+/// What one batch statement evaluated to, several values for a list.
+enum Answer {
+    Values(Vec<i32>),
+    Exact(Vec<Rational>),
+}
+
+impl Answer {
+    fn text(&self) -> String {
+        match self {
+            Answer::Values(vals) => format_values(vals, false),
+            Answer::Exact(vals) => format_rationals(vals),
+        }
+    }
+
+    /// A lone value as itself and a list as an array.
+    fn json(&self) -> serde_json::Value {
+        fn one_or_all<T: Serialize>(vals: &[T]) -> serde_json::Value {
+            match vals {
+                [val] => json!(val),
+                _ => json!(vals),
+            }
+        }
+        match self {
+            Answer::Values(vals) => one_or_all(vals),
+            Answer::Exact(vals) => one_or_all(vals),
+        }
     }
 }
 
-fn evaluate(expr: &Expr, env: &mut Environment) -> Result<i32> {
-    let out = match expr {
-        Expr::Number { n } => *n,
-        Expr::Variable { name } => env.lookup(name),
-        Expr::Assign { location, value } => match **location {
-            Expr::Variable { ref name } => {
-                let eval = evaluate(value, env)?;
-                env.assign(name, eval);
-                Ok(env.lookup(name))
+/// Evaluates each statement of a script against one environment, printing
+/// every result (unless `--silent`) and stopping at the first error, or
+/// with `--keep-going` reporting every error. Lines may hold several
+/// `;`-separated statements. Returns whether everything succeeded.
+fn run_batch(source: &str, opts: &Options) -> bool {
+    let mut env = opts.environment();
+    if opts.output == OutputFormat::Json {
+        env.capture_printed();
+    }
+    let color = opts.color.unwrap_or(ColorMode::Auto).enabled(false);
+    let mut ok = true;
+    for line in logical_lines(source) {
+        let statements = match tokenize(&line).and_then(|t| parse_program(&mut opts.parser(t))) {
+            Ok(statements) => statements,
+            Err(e) => {
+                print_outcome(line.trim(), &Err(e), opts, color, &mut env);
+                if !opts.keep_going {
+                    return false;
+                }
+                ok = false;
+                continue;
             }
-            _ => Err(Error::SyntaxError(format!("{}{}", location, value))),
-        }?,
-        Expr::Add { left, right } => evaluate(left, env)? + evaluate(right, env)?,
-        Expr::Minus { left, right } => evaluate(left, env)? - evaluate(right, env)?,
-        Expr::Mul { left, right } => evaluate(left, env)? * evaluate(right, env)?,
-    };
-    Ok(out)
+        };
+        let single = statements.len() == 1;
+        for statement in statements {
+            let out = if opts.exact {
+                evaluate_exact_all(&statement, &mut env).map(|out| {
+                    record_whole_answers(&out, &mut env);
+                    Answer::Exact(out)
+                })
+            } else {
+                evaluate_all(&statement, &mut env).map(|out| {
+                    out.iter().for_each(|&val| env.record_answer(val));
+                    Answer::Values(out)
+                })
+            };
+            let input = if single {
+                line.trim().to_string()
+            } else {
+                unparse(&statement)
+            };
+            print_outcome(&input, &out, opts, color, &mut env);
+            if out.is_err() {
+                if !opts.keep_going {
+                    return false;
+                }
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Reports one batch result in the `--output` format. `--silent` hides
+/// successes only. `env` gives `--explain-errors` hints and, in JSON, what
+/// the input printed.
+fn print_outcome(
+    input: &str,
+    out: &Result<Answer, Error>,
+    opts: &Options,
+    color: bool,
+    env: &mut Environment,
+) {
+    let printed = env.take_printed();
+    match (opts.output, out) {
+        (OutputFormat::Text, Ok(out)) if !opts.silent => {
+            println!("{}", paint(&out.text(), GREEN, color))
+        }
+        (OutputFormat::Text, Err(e)) => {
+            eprintln!("{}", paint(&e.to_string(), RED, color));
+            if let Some(hint) = opts.explain_errors.then(|| suggest_fix(e, env)).flatten() {
+                eprintln!("hint: {hint}");
+            }
+        }
+        (OutputFormat::Json, out) if !(opts.silent && out.is_ok()) => {
+            println!(
+                "{}",
+                result_to_json(input, out.as_ref().map(Answer::json), &printed)
+            )
+        }
+        _ => {}
+    }
+}
+
+/// Records the whole-valued exact results for `ans`. Fractions are
+/// skipped, since `ans` only holds integers.
+fn record_whole_answers(vals: &[Rational], env: &mut Environment) {
+    for val in vals.iter().filter(|val| val.is_integer()) {
+        env.record_answer(val.num());
+    }
 }
 
 fn main() {
-    let mut env = Environment::new();
+    let opts = parse_args();
+    let Some(input) = batch_input(&opts) else {
+        if opts.json_tokens {
+            usage_error("--json-tokens needs --eval, a script file or piped input");
+        }
+        if opts.ast_dot {
+            usage_error("--ast-dot needs --eval, a script file or piped input");
+        }
+        let interactive = io::stdout().is_terminal();
+        let mut config = ReplConfig {
+            color: ColorMode::Auto.enabled(interactive),
+            overrides: Overrides {
+                color: opts.color.map(|color| color.enabled(interactive)),
+                max_input_bytes: opts.max_input_bytes,
+                implicit_mul: opts.implicit_mul,
+            },
+            ..ReplConfig::default()
+        };
+        config.apply_overrides();
+        load_config(&mut config);
+        config.exact = opts.exact;
+        config.explain_errors = opts.explain_errors;
+        repl(opts.show_banner, opts.environment(), config);
+        return;
+    };
+
+    let max_input_bytes = opts.max_input_bytes.unwrap_or(DEFAULT_MAX_INPUT_BYTES);
+    if let Err(e) = check_input_len(&input, max_input_bytes) {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+    if opts.json_tokens {
+        match tokenize_with_comments(&input) {
+            Ok(tokens) => println!("{}", tokens_to_json(&tokens)),
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+    } else if opts.ast_dot {
+        match tokenize(&input).and_then(|t| parse_program(&mut opts.parser(t))) {
+            Ok(statements) => {
+                for statement in statements {
+                    println!("{}", expr_to_dot(&statement));
+                }
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+    } else if !run_batch(&input, &opts) {
+        process::exit(1);
+    }
+}
+
+/// The line `:watch` echoes when `name` is assigned `val`, if it's watched.
+fn watch_message(watched: &BTreeSet<String>, name: &str, val: i32) -> Option<String> {
+    watched.contains(name).then(|| format!("{name} -> {val}"))
+}
+
+fn repl(show_banner: bool, mut env: Environment, mut config: ReplConfig) {
+    let watched = Rc::clone(&config.watched);
+    env.on_assign = Some(Box::new(move |name, val| {
+        if let Some(line) = watch_message(&watched.borrow(), name, val) {
+            println!("{line}");
+        }
+    }));
+    if show_banner {
+        println!("{}", banner());
+    }
     loop {
         print!("calc > ");
         io::stdout().flush().unwrap();
@@ -266,35 +946,303 @@ fn main() {
             break;
         };
 
+        if let Err(e) = check_input_len(raw_calc.trim_end(), config.max_input_bytes) {
+            config.print_error(&e);
+            continue;
+        }
+
+        if raw_calc.trim().starts_with(':') {
+            run_command(raw_calc.trim(), &mut config, &mut env);
+            continue;
+        }
+
+        let raw_calc = match expand_macros(&raw_calc, &config.macros, config.max_input_bytes) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                config.print_error(&e);
+                continue;
+            }
+        };
         let tokens = match tokenize(&raw_calc) {
             Ok(tokens) => tokens,
             Err(e) => {
-                println!("{:?}", e);
+                config.print_error(&e);
                 continue;
             }
         };
-        println!("tokens: {:?}", tokens);
+        println!("tokens: {}", format_tokens(&tokens));
 
-        let mut p = Parser {
-            tokens: tokens.clone(),
-            n: 0,
-        };
+        let mut p = Parser::new(tokens.clone()).implicit_mul(config.implicit_mul);
 
         let parsed = match parse(&mut p) {
             Ok(parsed) => parsed,
             Err(e) => {
-                println!("{:?}", e);
+                config.print_error(&e);
                 continue;
             }
         };
         println!("parsed: {:?}", parsed);
-        let out = match evaluate(&parsed, &mut env) {
+        if config.exact {
+            match evaluate_exact_all(&parsed, &mut env) {
+                Ok(out) => {
+                    env.drop_assumptions();
+                    record_whole_answers(&out, &mut env);
+                    println!("{}", paint(&format_rationals(&out), GREEN, config.color));
+                }
+                Err(e) => config.print_eval_error(&e, &env),
+            }
+            continue;
+        }
+        let out = match evaluate_all(&parsed, &mut env) {
             Ok(out) => out,
             Err(e) => {
-                println!("{:?}", e);
+                config.print_eval_error(&e, &env);
                 continue;
             }
         };
-        println!("{out}");
+        env.drop_assumptions();
+        out.iter().for_each(|&val| env.record_answer(val));
+        config.print_values(&out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banner_includes_version() {
+        assert!(banner().contains(env!("CARGO_PKG_VERSION")));
+        assert!(banner().contains(":help"));
+    }
+
+    #[test]
+    fn funcs_listing_is_sorted_with_arity() {
+        let listing = format_funcs(&[
+            ("max", 2, 2),
+            ("abs", 1, 1),
+            ("log", 1, 2),
+            ("sum", 0, usize::MAX),
+        ]);
+        assert_eq!(
+            listing,
+            "abs 1 argument(s)\n\
+             log 1 to 2 argument(s)\n\
+             max 2 argument(s)\n\
+             sum at least 0 argument(s)"
+        );
+    }
+
+    #[test]
+    fn funcs_listing_pads_to_the_longest_name() {
+        let listing = format_funcs(&[("abs", 1, 1), ("trailing_zeros", 1, 1)]);
+        assert_eq!(
+            listing,
+            "abs            1 argument(s)\n\
+             trailing_zeros 1 argument(s)"
+        );
+        let listing = format_funcs(BUILTINS);
+        let column = |line: &str| line.len() - line.split_once(' ').unwrap().1.trim_start().len();
+        let first = column(listing.lines().next().unwrap());
+        assert!(
+            listing.lines().all(|line| column(line) == first),
+            "{listing}"
+        );
+    }
+
+    #[test]
+    fn env_output_recreates_the_variables() {
+        let mut env = Environment::new();
+        eval_str("width = 12", &mut env).unwrap();
+        eval_str("depth = -3", &mut env).unwrap();
+        let listing = format_env(&env);
+        assert_eq!(listing, "depth = -3;\nwidth = 12;");
+        let mut restored = Environment::new();
+        let statements = parse_program(&mut Parser::new(tokenize(&listing).unwrap())).unwrap();
+        for statement in &statements {
+            evaluate(statement, &mut restored).unwrap();
+        }
+        assert_eq!(restored.vars(), env.vars());
+    }
+
+    #[test]
+    fn script_lines_are_statements() {
+        let script = "width = 4\nheight = max(\n  2,\n  3\n)\n\nwidth * height";
+        let lines = logical_lines(script);
+        assert_eq!(
+            lines,
+            [
+                "width = 4",
+                "height = max(\n  2,\n  3\n)",
+                "",
+                "width * height"
+            ]
+        );
+        let mut env = Environment::new();
+        let results: Vec<i32> = lines
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| eval_str(line, &mut env).unwrap())
+            .collect();
+        assert_eq!(results, [4, 3, 12]);
+    }
+
+    #[test]
+    fn block_comments_carry_a_statement_over() {
+        assert_eq!(
+            logical_lines("1 + /* one\ntwo */ 2\n3"),
+            ["1 + /* one\ntwo */ 2", "3"]
+        );
+    }
+
+    #[test]
+    fn describe_reports_value_and_source() {
+        let mut env = Environment::new();
+        eval_str("x = 40 + 2", &mut env).unwrap();
+        env.define("y", 7).unwrap();
+        assert_eq!(
+            format_describe("x", &env),
+            "x = 42, last assigned from 40 + 2"
+        );
+        assert_eq!(format_describe("y", &env), "y = 7");
+        assert_eq!(
+            format_describe("max", &env),
+            "max is a built-in function taking 2 argument(s)"
+        );
+        assert_eq!(format_describe("z", &env), "z is not defined");
+    }
+
+    #[test]
+    fn answers_as_json() {
+        assert_eq!(Answer::Values(vec![5]).json(), json!(5));
+        assert_eq!(Answer::Values(vec![1, -2]).json(), json!([1, -2]));
+        let half = Rational::new(1, 2).unwrap();
+        let two = Rational::new(4, 2).unwrap();
+        assert_eq!(Answer::Exact(vec![half]).json(), json!("1/2"));
+        assert_eq!(Answer::Exact(vec![half, two]).json(), json!(["1/2", 2]));
+    }
+
+    #[test]
+    fn diff_of_two_parses() {
+        assert_eq!(
+            format_diff("2 + 3 * 4 | (2 + 3) * 4", false).unwrap(),
+            "differ at `2 + 3 * 4` vs `(2 + 3) * 4`"
+        );
+        assert_eq!(
+            format_diff("2 + 3 * 4 | 2 + (3 * 4)", false).unwrap(),
+            "same tree"
+        );
+        assert_eq!(
+            format_diff("1 + x | 1 + y", false).unwrap(),
+            "differ at `x` vs `y`"
+        );
+        assert!(format_diff("1 + 2", false).is_err());
+    }
+
+    #[test]
+    fn stats_honour_implicit_mul() {
+        assert!(parse_line_with_tokens("2x", false).is_err());
+        let (tokens, expr) = parse_line_with_tokens("2x", true).unwrap();
+        let stats = Stats::of(&tokens, &expr);
+        assert_eq!((stats.tokens, stats.operands, stats.nodes), (2, 2, 3));
+    }
+
+    #[test]
+    fn diff_honours_implicit_mul() {
+        assert!(format_diff("2x | 2 * x", false).is_err());
+        assert_eq!(format_diff("2x | 2 * x", true).unwrap(), "same tree");
+    }
+
+    #[test]
+    fn parse_shows_a_tree_that_would_not_evaluate() {
+        assert!(eval_str("2 + x", &mut Environment::new()).is_err());
+        let tree = format_parse("2 + x", false).unwrap();
+        assert!(tree.starts_with("Add {"), "{tree}");
+        assert!(tree.contains("name: \"x\""), "{tree}");
+        assert!(format_parse("2x", true).unwrap().starts_with("Mul {"));
+        assert!(format_parse("2 +", false).is_err());
+    }
+
+    #[test]
+    fn config_fields_present_absent_and_malformed() {
+        let mut config = ReplConfig::default();
+        let problems = apply_config(
+            "group = true\n# a comment\n\ncolor = \"always\"\nmax_input_bytes = 64\n",
+            &mut config,
+        );
+        assert!(problems.is_empty(), "{problems:?}");
+        assert!(config.group && config.color);
+        assert_eq!(config.max_input_bytes, 64);
+        // Absent settings are left as they were.
+        assert!(!config.implicit_mul);
+
+        let problems = apply_config(
+            "group = maybe\nmax_input_bytes = -1\nshade = 3\njust words\n",
+            &mut config,
+        );
+        assert_eq!(
+            problems,
+            [
+                "line 1: invalid value `maybe` for group",
+                "line 2: invalid value `-1` for max_input_bytes",
+                "line 3: unknown setting `shade`",
+                "line 4: expected key = value",
+            ]
+        );
+        assert!(config.group);
+        assert_eq!(config.max_input_bytes, 64);
+    }
+
+    #[test]
+    fn command_line_settings_survive_a_reload() {
+        let mut config = ReplConfig {
+            overrides: Overrides {
+                color: Some(false),
+                max_input_bytes: Some(10),
+                implicit_mul: true,
+            },
+            ..ReplConfig::default()
+        };
+        let file = "color = always\nmax_input_bytes = 99\nimplicit_mul = false\ngroup = true";
+        for _ in 0..2 {
+            assert!(apply_config(file, &mut config).is_empty());
+            assert!(!config.color);
+            assert_eq!(config.max_input_bytes, 10);
+            assert!(config.implicit_mul);
+            assert!(config.group);
+        }
+    }
+
+    #[test]
+    fn watching_and_unwatching() {
+        let mut config = ReplConfig::default();
+        let mut env = Environment::new();
+        run_command(":watch x y", &mut config, &mut env);
+        run_command(":unwatch y", &mut config, &mut env);
+        let watched = config.watched.borrow();
+        assert_eq!(watched.iter().collect::<Vec<_>>(), ["x"]);
+        assert_eq!(watch_message(&watched, "x", 3).unwrap(), "x -> 3");
+        assert_eq!(watch_message(&watched, "y", 3), None);
+    }
+
+    #[test]
+    fn format_prints_canonical_spacing_and_minimal_parentheses() {
+        let format = |source: &str| unparse(&parse_line(source, false).unwrap());
+        assert_eq!(format("2+3*4"), "2 + 3 * 4");
+        assert_eq!(format("(2+3)*4"), "(2 + 3) * 4");
+        assert_eq!(format("((2))+(3*4)"), "2 + 3 * 4");
+        assert_eq!(format("1-(2-3)"), "1 - (2 - 3)");
+        assert_eq!(format("(2^3)^2"), "(2 ^ 3) ^ 2");
+        assert_eq!(format("x=max( 1 ,2 )!"), "x = max(1, 2)!");
+        assert_eq!(unparse(&parse_line("2x", true).unwrap()), "2 * x");
+    }
+
+    #[test]
+    fn definitions_take_names_with_digits_and_underscores() {
+        let (name, expr) = parse_definition(":alias", "area_2 = w1 * h").unwrap();
+        assert_eq!(name, "area_2");
+        assert_eq!(unparse(&expr), "w1 * h");
+        assert!(parse_definition(":alias", "2x = 1").is_err());
+        assert!(parse_definition(":alias", " = 1").is_err());
     }
 }
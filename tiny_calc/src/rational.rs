@@ -0,0 +1,353 @@
+use crate::error::{Error, Result};
+use crate::{
+    CompareOp, Environment, Expr, builtins, evaluate_alias, factorial, remainder, unparse,
+};
+use serde::{Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// An exact fraction in lowest terms, with the sign on the numerator, so
+/// `2/4` and `-1/-2` are stored as `1/2`. Both parts are i32 like the
+/// integer values; results that don't reduce into that range overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rational {
+    num: i32,
+    den: i32,
+}
+
+impl Rational {
+    pub fn new(num: i32, den: i32) -> Result<Self> {
+        Self::reduce(i64::from(num), i64::from(den), || format!("{num}/{den}"))
+    }
+    pub fn num(self) -> i32 {
+        self.num
+    }
+    pub fn den(self) -> i32 {
+        self.den
+    }
+    pub fn is_integer(self) -> bool {
+        self.den == 1
+    }
+    /// The value as an integer for `what`, which only works on whole
+    /// numbers, or a `MathError` if it isn't one.
+    pub(crate) fn whole(self, what: &str) -> Result<i32> {
+        if !self.is_integer() {
+            return Err(Error::MathError(format!(
+                "{what} needs whole numbers, got {self}"
+            )));
+        }
+        Ok(self.num)
+    }
+    pub fn checked_add(self, other: Self) -> Result<Self> {
+        let (a, b, c, d) = self.widen(other);
+        Self::reduce(a * d + c * b, b * d, || format!("{self} + {other}"))
+    }
+    pub fn checked_sub(self, other: Self) -> Result<Self> {
+        let (a, b, c, d) = self.widen(other);
+        Self::reduce(a * d - c * b, b * d, || format!("{self} - {other}"))
+    }
+    pub fn checked_mul(self, other: Self) -> Result<Self> {
+        let (a, b, c, d) = self.widen(other);
+        Self::reduce(a * c, b * d, || format!("{self} * {other}"))
+    }
+    pub fn checked_div(self, other: Self) -> Result<Self> {
+        let (a, b, c, d) = self.widen(other);
+        Self::reduce(a * d, b * c, || format!("{self} / {other}"))
+    }
+    pub fn checked_neg(self) -> Result<Self> {
+        Self::reduce(-i64::from(self.num), i64::from(self.den), || {
+            format!("-{self}")
+        })
+    }
+    /// Raises to an integer power; a negative `exp` inverts first, so
+    /// `(2/3) ^ -2 == 9/4`.
+    pub fn checked_pow(self, exp: Self) -> Result<Self> {
+        if !exp.is_integer() {
+            return Err(Error::MathError(format!(
+                "fractional exponent {exp} has no exact result"
+            )));
+        }
+        let base = if exp.num < 0 {
+            Self::new(1, 1)?.checked_div(self)?
+        } else {
+            self
+        };
+        let overflow = || Error::Overflow(format!("{self} ^ {exp}"));
+        let e = exp.num.unsigned_abs();
+        let num = base.num.checked_pow(e).ok_or_else(overflow)?;
+        let den = base.den.checked_pow(e).ok_or_else(overflow)?;
+        Ok(Self { num, den })
+    }
+    fn widen(self, other: Self) -> (i64, i64, i64, i64) {
+        (
+            i64::from(self.num),
+            i64::from(self.den),
+            i64::from(other.num),
+            i64::from(other.den),
+        )
+    }
+    fn reduce(num: i64, den: i64, what: impl Fn() -> String) -> Result<Self> {
+        if den == 0 {
+            return Err(Error::DivisionByZero);
+        }
+        let g = gcd(num, den) * den.signum();
+        let overflow = |_| Error::Overflow(what());
+        Ok(Self {
+            num: i32::try_from(num / g).map_err(overflow)?,
+            den: i32::try_from(den / g).map_err(overflow)?,
+        })
+    }
+}
+
+/// Denominators are always positive, so cross-multiplying keeps the order.
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b, c, d) = self.widen(*other);
+        (a * d).cmp(&(c * b))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<i32> for Rational {
+    fn from(n: i32) -> Self {
+        Self { num: n, den: 1 }
+    }
+}
+
+/// `n` for whole numbers, otherwise `num/den` such as `-2/3`.
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+/// Whole numbers serialize as numbers and fractions as strings such as
+/// `"2/3"`, since JSON has no fractions.
+impl Serialize for Rational {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.is_integer() {
+            serializer.serialize_i32(self.num)
+        } else {
+            serializer.collect_str(self)
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Evaluates `expr` with exact fractions throughout, so `1/3 + 1/3` is
+/// `2/3` rather than `0` and `(1/3 + 1/3) > 0` holds. Comparisons, `not`
+/// and the built-ins that only order or add their arguments, such as `abs`
+/// and `max`, work on fractions too. What only makes sense for integers,
+/// such as `%`, `!` and the bit counts, is a `MathError` when handed a
+/// value that isn't whole. Variables hold i32, so assigning one is too.
+pub fn evaluate_exact(expr: &Expr, env: &mut Environment) -> Result<Rational> {
+    match expr {
+        Expr::Number { n } => Ok(Rational::from(*n)),
+        Expr::Variable { name } => read_variable(name, env),
+        Expr::Group { inner } => evaluate_exact(inner, env),
+        Expr::Assign { location, value } => assign(location, value, env),
+        Expr::Compare { first, rest } => compare(first, rest, env),
+        Expr::Add { left, right } => binary(left, right, env, Rational::checked_add),
+        Expr::Minus { left, right } => binary(left, right, env, Rational::checked_sub),
+        Expr::Mul { left, right } => binary(left, right, env, Rational::checked_mul),
+        Expr::Div { left, right } => binary(left, right, env, Rational::checked_div),
+        Expr::Pow { left, right } => binary(left, right, env, Rational::checked_pow),
+        Expr::Mod { left, right } => modulo(left, right, env),
+        Expr::Call { name, args } => call(name, args, env),
+        Expr::Neg { operand } => evaluate_exact(operand, env).and_then(Rational::checked_neg),
+        Expr::Factorial { operand } => evaluate_exact(operand, env)
+            .and_then(|val| factorial(val.whole("!")?))
+            .map(Rational::from),
+        Expr::Not { operand } => {
+            evaluate_exact(operand, env).map(|val| Rational::from(i32::from(val.num == 0)))
+        }
+        Expr::List { items } => {
+            let mut last = Rational::from(0);
+            for item in items {
+                last = evaluate_exact(item, env)?;
+            }
+            Ok(last)
+        }
+    }
+}
+
+fn read_variable(name: &str, env: &mut Environment) -> Result<Rational> {
+    if !env.contains(name) && env.aliases.contains_key(name) {
+        evaluate_alias(name, env, evaluate_exact)
+    } else {
+        env.lookup(name).map(Rational::from)
+    }
+}
+
+fn assign(location: &Expr, value: &Expr, env: &mut Environment) -> Result<Rational> {
+    match location {
+        Expr::Variable { name } => {
+            let val = evaluate_exact(value, env)?;
+            if !val.is_integer() {
+                return Err(Error::MathError(format!(
+                    "can't store {val} in {name}: variables hold whole numbers"
+                )));
+            }
+            env.assign(name, val.num)?;
+            env.sources.insert(name.clone(), unparse(value));
+            Ok(val)
+        }
+        _ => Err(Error::SyntaxError(format!(
+            "can't assign to `{}`",
+            unparse(location)
+        ))),
+    }
+}
+
+fn compare(first: &Expr, rest: &[(CompareOp, Expr)], env: &mut Environment) -> Result<Rational> {
+    let mut left = evaluate_exact(first, env)?;
+    for (i, (op, operand)) in rest.iter().enumerate() {
+        let right = evaluate_exact(operand, env)?;
+        if !op.holds(left, right) {
+            if let Some(skipped) = &mut env.skipped {
+                *skipped += rest.len() - i - 1;
+            }
+            return Ok(Rational::from(0));
+        }
+        left = right;
+    }
+    Ok(Rational::from(1))
+}
+
+fn binary(
+    left: &Expr,
+    right: &Expr,
+    env: &mut Environment,
+    op: fn(Rational, Rational) -> Result<Rational>,
+) -> Result<Rational> {
+    let a = evaluate_exact(left, env)?;
+    let b = evaluate_exact(right, env)?;
+    op(a, b)
+}
+
+fn modulo(left: &Expr, right: &Expr, env: &mut Environment) -> Result<Rational> {
+    let a = evaluate_exact(left, env)?.whole("%")?;
+    let b = evaluate_exact(right, env)?.whole("%")?;
+    remainder(a, b, env.division).map(Rational::from)
+}
+
+fn call(name: &str, args: &[Expr], env: &mut Environment) -> Result<Rational> {
+    builtins::check_arity(name, args.len())?;
+    if name == "repeat" {
+        return builtins::repeat_exact(&args[0], &args[1], env);
+    }
+    let args = args
+        .iter()
+        .map(|arg| evaluate_exact(arg, env))
+        .collect::<Result<Vec<Rational>>>()?;
+    builtins::call_exact(name, &args, env)
+}
+
+/// `evaluate_all` for `evaluate_exact`: one value per item of a list.
+pub fn evaluate_exact_all(expr: &Expr, env: &mut Environment) -> Result<Vec<Rational>> {
+    match expr {
+        Expr::List { items } => items.iter().map(|item| evaluate_exact(item, env)).collect(),
+        _ => Ok(vec![evaluate_exact(expr, env)?]),
+    }
+}
+
+/// Renders exact values like `format_values`: one alone, several as
+/// `[1/2, 3]`.
+pub fn format_rationals(vals: &[Rational]) -> String {
+    match vals {
+        [val] => val.to_string(),
+        _ => {
+            let vals: Vec<String> = vals.iter().map(Rational::to_string).collect();
+            format!("[{}]", vals.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Parser, parse, tokenize};
+
+    fn exact_in(source: &str, env: &mut Environment) -> Result<String> {
+        let expr = parse(&mut Parser::new(tokenize(source)?))?;
+        Ok(evaluate_exact(&expr, env)?.to_string())
+    }
+
+    fn exact(source: &str) -> Result<String> {
+        exact_in(source, &mut Environment::new())
+    }
+
+    #[test]
+    fn assigning_to_a_non_variable_names_the_target() {
+        let message = exact("(1 + 2) = 4").unwrap_err().to_string();
+        assert!(message.contains("can't assign to `1 + 2`"), "{message}");
+    }
+
+    #[test]
+    fn addition_and_division_give_reduced_fractions() {
+        assert_eq!(exact("1/3 + 1/3").unwrap(), "2/3");
+        assert_eq!(exact("6/8").unwrap(), "3/4");
+        assert_eq!(exact("1/-2").unwrap(), "-1/2");
+        assert_eq!(exact("1/2 + 1/2").unwrap(), "1");
+        assert_eq!(exact("(2/3) ^ -2").unwrap(), "9/4");
+        assert!(matches!(exact("1/(1/2 - 1/2)"), Err(Error::DivisionByZero)));
+    }
+
+    #[test]
+    fn nothing_is_truncated_along_the_way() {
+        assert_eq!(exact("abs(1/2 - 1)").unwrap(), "1/2");
+        assert_eq!(exact("(1/3 + 1/3) > 0").unwrap(), "1");
+        assert_eq!(exact("1/3 < 1/2 < 2/3").unwrap(), "1");
+        assert_eq!(exact("1/3 == 2/6").unwrap(), "1");
+        assert_eq!(exact("not (1/2)").unwrap(), "0");
+        assert_eq!(exact("max(1/2, 1/3) + min(1/2, 1/3)").unwrap(), "5/6");
+        assert_eq!(exact("sign(-1/2)").unwrap(), "-1");
+        assert_eq!(exact("clamp(1/2, 0, 1/3)").unwrap(), "1/3");
+        assert_eq!(exact("sum(1/2, 1/3, 1/6)").unwrap(), "1");
+        assert_eq!(exact("repeat(1/4, 2)").unwrap(), "1/2");
+        assert_eq!(exact("1/2, 1/4").unwrap(), "1/4");
+        let mut env = Environment::new();
+        let half = parse(&mut Parser::new(tokenize("1/2").unwrap())).unwrap();
+        env.define_alias("half", half);
+        assert_eq!(exact_in("half + half / 2", &mut env).unwrap(), "3/4");
+    }
+
+    #[test]
+    fn integer_only_operations_refuse_fractions() {
+        for source in [
+            "(1/2) % 2",
+            "3 % (1/2)",
+            "(1/2)!",
+            "bit_count(1/2)",
+            "log(1/2)",
+            "repeat(1, 1/2)",
+            "x = 1/2",
+        ] {
+            assert!(
+                matches!(exact(source), Err(Error::MathError(_))),
+                "{source} gave {:?}",
+                exact(source)
+            );
+        }
+        assert_eq!(exact("7 % 3 + 3! + bit_count(4/4)").unwrap(), "8");
+        let mut env = Environment::new();
+        assert_eq!(exact_in("x = 6/3", &mut env).unwrap(), "2");
+        assert_eq!(env.lookup("x").unwrap(), 2);
+    }
+}
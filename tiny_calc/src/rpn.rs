@@ -0,0 +1,237 @@
+use crate::error::{Error, Result};
+use crate::{CompareOp, Environment, Expr, builtins, evaluate, is_name, taller};
+
+/// Converts an expression to reverse Polish notation by post-order
+/// traversal, e.g. `2 + 3 * 4` gives `["2", "3", "4", "*", "+"]`.
+///
+/// Unary minus is written `neg` so it can't be mistaken for subtraction, and
+/// a call emits its arguments followed by the function name. RPN has no way
+/// to reuse a value, so a chain `a < b < c` becomes `a b < b c < min`: each
+/// link is compared on its own and `min` ands the 0/1 results. The middle
+/// operand is written, and so evaluated, twice.
+///
+/// A list's items are written one after another, leaving one value each on
+/// the stack, so `eval_rpn` won't accept the result.
+pub fn to_postfix(expr: &Expr) -> Vec<String> {
+    let mut out = Vec::new();
+    push_postfix(expr, &mut out);
+    out
+}
+
+fn push_postfix(expr: &Expr, out: &mut Vec<String>) {
+    match expr {
+        Expr::Number { n } => out.push(n.to_string()),
+        Expr::Variable { name } => out.push(name.clone()),
+        Expr::Assign { location, value } => push_binary(location, value, "=", out),
+        Expr::Compare { first, rest } => {
+            let mut left = &**first;
+            for (i, (op, right)) in rest.iter().enumerate() {
+                push_binary(left, right, op.symbol(), out);
+                if i > 0 {
+                    out.push("min".to_string());
+                }
+                left = right;
+            }
+        }
+        Expr::Add { left, right } => push_binary(left, right, "+", out),
+        Expr::Minus { left, right } => push_binary(left, right, "-", out),
+        Expr::Mul { left, right } => push_binary(left, right, "*", out),
+        Expr::Div { left, right } => push_binary(left, right, "/", out),
+        Expr::Mod { left, right } => push_binary(left, right, "%", out),
+        Expr::Pow { left, right } => push_binary(left, right, "^", out),
+        Expr::Call { name, args } => {
+            for arg in args {
+                push_postfix(arg, out);
+            }
+            out.push(name.clone());
+        }
+        Expr::Neg { operand } => push_unary(operand, "neg", out),
+        Expr::Factorial { operand } => push_unary(operand, "!", out),
+        Expr::Not { operand } => push_unary(operand, "not", out),
+        Expr::Group { inner } => push_postfix(inner, out),
+        Expr::List { items } => {
+            for item in items {
+                push_postfix(item, out);
+            }
+        }
+    }
+}
+
+fn push_binary(left: &Expr, right: &Expr, op: &str, out: &mut Vec<String>) {
+    push_postfix(left, out);
+    push_postfix(right, out);
+    out.push(op.to_string());
+}
+
+fn push_unary(operand: &Expr, op: &str, out: &mut Vec<String>) {
+    push_postfix(operand, out);
+    out.push(op.to_string());
+}
+
+/// Evaluates a reverse Polish token sequence, the inverse of `to_postfix`.
+/// The tokens are rebuilt into an `Expr` with a stack and then run through
+/// the normal evaluator, so each operator behaves exactly as in infix.
+pub fn eval_rpn(tokens: &[&str], env: &mut Environment) -> Result<i32> {
+    evaluate(&rpn_to_expr(tokens)?, env)
+}
+
+/// Rebuilds the tree with a stack of operands, each held with the height
+/// of its tree so that the tree can be capped at `MAX_HEIGHT` while
+/// building, as the infix parser does.
+fn rpn_to_expr(tokens: &[&str]) -> Result<Expr> {
+    let mut stack: Vec<(Expr, usize)> = Vec::new();
+    for &token in tokens {
+        let (expr, height) = if let Ok(n) = token.parse() {
+            (Expr::Number { n }, 1)
+        } else if let Some((min, max)) = builtins::arity(token) {
+            if min != max {
+                return Err(Error::SyntaxError(format!(
+                    "{token} takes a variable number of arguments and can't be used in RPN"
+                )));
+            }
+            let (args, height) = pop_operands(&mut stack, min, token)?;
+            let name = token.to_string();
+            (Expr::Call { name, args }, height)
+        } else {
+            match token {
+                _ if CompareOp::from_symbol(token).is_some() => {
+                    let (operands, height) = pop_operands(&mut stack, 2, token)?;
+                    let mut operands = operands.into_iter();
+                    let first = Box::new(operands.next().unwrap());
+                    let op = CompareOp::from_symbol(token).unwrap();
+                    let rest = vec![(op, operands.next().unwrap())];
+                    (Expr::Compare { first, rest }, height)
+                }
+                "+" | "-" | "*" | "/" | "%" | "^" | "=" => {
+                    let (operands, height) = pop_operands(&mut stack, 2, token)?;
+                    let mut operands = operands.into_iter();
+                    let left = Box::new(operands.next().unwrap());
+                    let right = Box::new(operands.next().unwrap());
+                    let expr = match token {
+                        "+" => Expr::Add { left, right },
+                        "-" => Expr::Minus { left, right },
+                        "*" => Expr::Mul { left, right },
+                        "/" => Expr::Div { left, right },
+                        "%" => Expr::Mod { left, right },
+                        "^" => Expr::Pow { left, right },
+                        _ => Expr::Assign {
+                            location: left,
+                            value: right,
+                        },
+                    };
+                    (expr, height)
+                }
+                "neg" | "!" | "not" => {
+                    let (mut operands, height) = pop_operands(&mut stack, 1, token)?;
+                    let operand = Box::new(operands.remove(0));
+                    let expr = match token {
+                        "neg" => Expr::Neg { operand },
+                        "!" => Expr::Factorial { operand },
+                        _ => Expr::Not { operand },
+                    };
+                    (expr, height)
+                }
+                _ if is_name(token) => {
+                    let name = token.to_string();
+                    (Expr::Variable { name }, 1)
+                }
+                _ => {
+                    return Err(Error::SyntaxError(format!(
+                        "Couldn't parse {token} as an RPN token"
+                    )));
+                }
+            }
+        };
+        stack.push((expr, height));
+    }
+    match stack.len() {
+        1 => Ok(stack.pop().unwrap().0),
+        0 => Err(Error::SyntaxError("Empty RPN expression".to_string())),
+        n => Err(Error::SyntaxError(format!(
+            "{n} operands left on the stack, expected 1"
+        ))),
+    }
+}
+
+/// Pops the `count` topmost operands for `op`, in their original order,
+/// with the height of the node that will hold them.
+fn pop_operands(
+    stack: &mut Vec<(Expr, usize)>,
+    count: usize,
+    op: &str,
+) -> Result<(Vec<Expr>, usize)> {
+    if stack.len() < count {
+        return Err(Error::SyntaxError(format!(
+            "Stack underflow at {op}: needs {count} operand(s), found {}",
+            stack.len()
+        )));
+    }
+    let operands = stack.split_off(stack.len() - count);
+    let height = taller(operands.iter().map(|&(_, h)| h).max().unwrap_or(0))?;
+    Ok((operands.into_iter().map(|(e, _)| e).collect(), height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAX_HEIGHT, Parser, parse, tokenize};
+
+    fn postfix(source: &str) -> Vec<String> {
+        to_postfix(&parse(&mut Parser::new(tokenize(source).unwrap())).unwrap())
+    }
+
+    #[test]
+    fn postfix_follows_precedence() {
+        assert_eq!(postfix("2 + 3 * 4"), ["2", "3", "4", "*", "+"]);
+        assert_eq!(postfix("(2 + 3) * 4"), ["2", "3", "+", "4", "*"]);
+        assert_eq!(postfix("8 - (3 - 2)"), ["8", "3", "2", "-", "-"]);
+        assert_eq!(postfix("2 ^ 3 ^ 2"), ["2", "3", "2", "^", "^"]);
+    }
+
+    #[test]
+    fn postfix_of_unary_calls_and_chains() {
+        assert_eq!(postfix("-x + 3!"), ["x", "neg", "3", "!", "+"]);
+        assert_eq!(postfix("max(1, 2 * 3)"), ["1", "2", "3", "*", "max"]);
+        assert_eq!(postfix("x = 1"), ["x", "1", "="]);
+        assert_eq!(postfix("1 < 2 < 3"), ["1", "2", "<", "2", "3", "<", "min"]);
+    }
+
+    fn rpn(tokens: &[&str]) -> Result<i32> {
+        eval_rpn(tokens, &mut Environment::new())
+    }
+
+    #[test]
+    fn valid_rpn() {
+        assert_eq!(rpn(&["2", "3", "4", "*", "+"]).unwrap(), 14);
+        assert_eq!(rpn(&["2", "3", "+", "4", "*"]).unwrap(), 20);
+        assert_eq!(rpn(&["5", "neg", "abs", "3", "!", "-"]).unwrap(), -1);
+        assert_eq!(rpn(&["1", "2", "<"]).unwrap(), 1);
+        let mut env = Environment::new();
+        assert_eq!(eval_rpn(&["x", "7", "="], &mut env).unwrap(), 7);
+        assert_eq!(eval_rpn(&["x", "x", "*"], &mut env).unwrap(), 49);
+    }
+
+    #[test]
+    fn malformed_rpn() {
+        let message = |tokens: &[&str]| rpn(tokens).unwrap_err().to_string();
+        assert!(message(&["1", "+"]).contains("Stack underflow at +"));
+        assert!(message(&["1", "2"]).contains("2 operands left on the stack"));
+        assert!(message(&[]).contains("Empty RPN expression"));
+        assert!(message(&["1", "@"]).contains("Couldn't parse @"));
+        assert!(message(&["1", "2", "sum"]).contains("variable number of arguments"));
+    }
+
+    #[test]
+    fn rpn_height_is_capped() {
+        let mut tokens = vec!["1"];
+        tokens.extend(std::iter::repeat_n("neg", MAX_HEIGHT - 1));
+        assert_eq!(rpn(&tokens).unwrap(), -1);
+        tokens.extend(std::iter::repeat_n("neg", 2_000_000));
+        assert!(
+            rpn(&tokens)
+                .unwrap_err()
+                .to_string()
+                .contains("levels tall")
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::error::{Error, Result};
+use crate::{CompareOp, Expr, MAX_NESTING, is_name, parse_number};
+
+/// Renders the tree as a Lisp-style S-expression, operator first, e.g.
+/// `2 + 3 * 4` gives `(+ 2 (* 3 4))`. Groups vanish, since the nesting
+/// already shows them; unary minus is `(- x)`, a call is `(name args...)`
+/// and a list is `(list items...)`. A chain `a < b <= c` becomes
+/// `(and (< a b) (<= b c))`, writing the middle operand twice.
+pub fn to_sexpr(expr: &Expr) -> String {
+    let (head, operands): (String, Vec<&Expr>) = match expr {
+        Expr::Number { n } => return n.to_string(),
+        Expr::Variable { name } => return name.clone(),
+        Expr::Group { inner } => return to_sexpr(inner),
+        Expr::Compare { first, rest } => {
+            let mut left = &**first;
+            let links: Vec<String> = rest
+                .iter()
+                .map(|(op, right)| {
+                    let link = format!("({} {} {})", op.symbol(), to_sexpr(left), to_sexpr(right));
+                    left = right;
+                    link
+                })
+                .collect();
+            return match links.as_slice() {
+                [link] => link.clone(),
+                _ => format!("(and {})", links.join(" ")),
+            };
+        }
+        Expr::Call { name, args } => (name.clone(), args.iter().collect()),
+        Expr::Assign { .. } => ("=".to_string(), expr.children()),
+        Expr::Add { .. } => ("+".to_string(), expr.children()),
+        Expr::Minus { .. } | Expr::Neg { .. } => ("-".to_string(), expr.children()),
+        Expr::Mul { .. } => ("*".to_string(), expr.children()),
+        Expr::Div { .. } => ("/".to_string(), expr.children()),
+        Expr::Mod { .. } => ("%".to_string(), expr.children()),
+        Expr::Pow { .. } => ("^".to_string(), expr.children()),
+        Expr::Factorial { .. } => ("!".to_string(), expr.children()),
+        Expr::Not { .. } => ("not".to_string(), expr.children()),
+        Expr::List { .. } => ("list".to_string(), expr.children()),
+    };
+    let mut parts = vec![head];
+    parts.extend(operands.into_iter().map(to_sexpr));
+    format!("({})", parts.join(" "))
+}
+
+/// Reads the S-expression syntax `to_sexpr` writes, e.g. `(+ 2 (* 3 4))`,
+/// into the same tree the infix parser builds, so either front-end feeds
+/// `evaluate`. Numbers may carry a leading `-`; a head that isn't an
+/// operator, `and` or `list` is a function call.
+pub fn parse_sexpr(input: &str) -> Result<Expr> {
+    let atoms = split_atoms(input);
+    let mut n = 0;
+    let expr = read_form(&atoms, &mut n, 0)?;
+    match atoms.get(n) {
+        None => Ok(expr),
+        Some(&(")", column)) => Err(unmatched_close(column)),
+        Some(&(atom, column)) => Err(Error::SyntaxError(format!(
+            "unexpected {atom} at column {column} after a complete expression"
+        ))),
+    }
+}
+
+/// Splits `input` into parentheses and the atoms between them, each with
+/// its 1-based column.
+fn split_atoms(input: &str) -> Vec<(&str, usize)> {
+    let mut atoms = Vec::new();
+    let mut start = None;
+    for (column, (i, c)) in input.char_indices().enumerate() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if let Some((from, from_column)) = start.take() {
+                atoms.push((&input[from..i], from_column));
+            }
+            if !c.is_whitespace() {
+                atoms.push((&input[i..i + 1], column + 1));
+            }
+        } else if start.is_none() {
+            start = Some((i, column + 1));
+        }
+    }
+    if let Some((from, from_column)) = start {
+        atoms.push((&input[from..], from_column));
+    }
+    atoms
+}
+
+fn read_form(atoms: &[(&str, usize)], n: &mut usize, depth: usize) -> Result<Expr> {
+    if depth > MAX_NESTING {
+        return Err(Error::SyntaxError(format!(
+            "expression nested more than {MAX_NESTING} levels deep"
+        )));
+    }
+    let Some(&(atom, column)) = atoms.get(*n) else {
+        return Err(Error::SyntaxError("Empty S-expression".to_string()));
+    };
+    *n += 1;
+    match atom {
+        ")" => Err(unmatched_close(column)),
+        "(" => {
+            let head = match atoms.get(*n) {
+                Some(&(head, _)) if head != "(" && head != ")" => head,
+                _ => {
+                    return Err(Error::SyntaxError(format!(
+                        "( at column {column} needs an operator or function name first"
+                    )));
+                }
+            };
+            *n += 1;
+            let mut args = Vec::new();
+            loop {
+                match atoms.get(*n) {
+                    Some(&(")", _)) => break,
+                    Some(_) => args.push(read_form(atoms, n, depth + 1)?),
+                    None => {
+                        return Err(Error::SyntaxError(format!(
+                            "( at column {column} is not closed"
+                        )));
+                    }
+                }
+            }
+            *n += 1;
+            build_form(head, args)
+        }
+        _ if atom
+            .trim_start_matches('-')
+            .starts_with(|c: char| c.is_ascii_digit()) =>
+        {
+            Ok(Expr::Number {
+                n: parse_number(atom)?,
+            })
+        }
+        _ if is_name(atom) => Ok(Expr::Variable {
+            name: atom.to_string(),
+        }),
+        _ => Err(Error::SyntaxError(format!(
+            "Couldn't parse {atom} at column {column} as a number or name"
+        ))),
+    }
+}
+
+/// Turns `(head args...)` into the matching `Expr`.
+fn build_form(head: &str, args: Vec<Expr>) -> Result<Expr> {
+    let mut operands = args.into_iter();
+    let expr = match (head, operands.len()) {
+        ("-" | "!" | "not", 1) => {
+            let operand = Box::new(operands.next().unwrap());
+            match head {
+                "-" => Expr::Neg { operand },
+                "!" => Expr::Factorial { operand },
+                _ => Expr::Not { operand },
+            }
+        }
+        ("+" | "-" | "*" | "/" | "%" | "^" | "=", 2) => {
+            let left = Box::new(operands.next().unwrap());
+            let right = Box::new(operands.next().unwrap());
+            match head {
+                "+" => Expr::Add { left, right },
+                "-" => Expr::Minus { left, right },
+                "*" => Expr::Mul { left, right },
+                "/" => Expr::Div { left, right },
+                "%" => Expr::Mod { left, right },
+                "^" => Expr::Pow { left, right },
+                _ => Expr::Assign {
+                    location: left,
+                    value: right,
+                },
+            }
+        }
+        (_, 2) if CompareOp::from_symbol(head).is_some() => Expr::Compare {
+            first: Box::new(operands.next().unwrap()),
+            rest: vec![(
+                CompareOp::from_symbol(head).unwrap(),
+                operands.next().unwrap(),
+            )],
+        },
+        ("and", _) => join_chain(operands.collect())?,
+        ("list", _) => Expr::List {
+            items: operands.collect(),
+        },
+        _ if !is_name(head) && !is_operator(head) => {
+            return Err(Error::SyntaxError(format!("unknown operator {head}")));
+        }
+        (_, got) if !is_name(head) || head == "not" => {
+            return Err(Error::SyntaxError(format!(
+                "{head} can't take {got} operand(s)"
+            )));
+        }
+        _ => Expr::Call {
+            name: head.to_string(),
+            args: operands.collect(),
+        },
+    };
+    Ok(expr)
+}
+
+/// Rebuilds a chain `a < b <= c` from `(and (< a b) (<= b c))`, the form
+/// `to_sexpr` gives it. Each link must start where the one before ended.
+fn join_chain(links: Vec<Expr>) -> Result<Expr> {
+    let not_a_chain = || {
+        Error::SyntaxError(
+            "and only joins comparisons like (and (< a b) (< b c)) into a chain".to_string(),
+        )
+    };
+    let mut links = links.into_iter();
+    let Some(Expr::Compare { first, mut rest }) = links.next() else {
+        return Err(not_a_chain());
+    };
+    for link in links {
+        match link {
+            Expr::Compare {
+                first: left,
+                rest: link_rest,
+            } if rest.last().is_some_and(|(_, right)| *right == *left) => rest.extend(link_rest),
+            _ => return Err(not_a_chain()),
+        }
+    }
+    Ok(Expr::Compare { first, rest })
+}
+
+fn unmatched_close(column: usize) -> Error {
+    Error::SyntaxError(format!("unmatched ) at column {column}"))
+}
+
+fn is_operator(head: &str) -> bool {
+    ["+", "-", "*", "/", "%", "^", "=", "!"].contains(&head)
+        || CompareOp::from_symbol(head).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Parser, parse, tokenize};
+
+    fn sexpr(source: &str) -> String {
+        to_sexpr(&parse(&mut Parser::new(tokenize(source).unwrap())).unwrap())
+    }
+
+    #[test]
+    fn arithmetic_assignment_and_variable_trees() {
+        assert_eq!(sexpr("2 + 3 * 4"), "(+ 2 (* 3 4))");
+        assert_eq!(sexpr("(2 + 3) * 4"), "(* (+ 2 3) 4)");
+        assert_eq!(sexpr("-x ^ 2!"), "(- (^ x (! 2)))");
+        assert_eq!(sexpr("x = y - 1"), "(= x (- y 1))");
+        assert_eq!(sexpr("x"), "x");
+        assert_eq!(sexpr("max(a, -2)"), "(max a -2)");
+        assert_eq!(sexpr("1 < x <= 3"), "(and (< 1 x) (<= x 3))");
+        assert_eq!(sexpr("1, 2"), "(list 1 2)");
+    }
+
+    #[test]
+    fn infix_to_sexpr_and_back_evaluates_the_same() {
+        use crate::{Environment, eval_str, evaluate};
+        for source in [
+            "2 + 3 * 4",
+            "(2 + 3) * 4",
+            "-7 / 2 % 3",
+            "2 ^ 3 ^ 2",
+            "max(1, abs(-4)) - 3!",
+            "1 < 2 <= 2 > 0",
+            "not 0 + 5",
+        ] {
+            let expected = eval_str(source, &mut Environment::new()).unwrap();
+            let reread = parse_sexpr(&sexpr(source)).unwrap();
+            assert_eq!(to_sexpr(&reread), sexpr(source), "{source}");
+            assert_eq!(
+                evaluate(&reread, &mut Environment::new()).unwrap(),
+                expected,
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn unbalanced_parentheses_are_reported() {
+        let message = |input: &str| parse_sexpr(input).unwrap_err().to_string();
+        assert!(message("(+ 1 2").contains("not closed"));
+        assert!(message("(+ 1 2))").contains("column 8"));
+        assert!(message(")").contains("column 1"));
+        assert!(message("(+ 1 2) 3").contains("after a complete expression"));
+        assert!(message("((+ 1 2))").contains("needs an operator"));
+        assert!(message("(1 2)").contains("unknown operator 1"));
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the calculator with `args`, feeding it `stdin`.
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust_calc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the calculator binary runs");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8(out.stdout.clone()).unwrap()
+}
+
+#[test]
+fn json_tokens_is_an_array_of_token_objects() {
+    let out = run(&["--json-tokens", "--eval", "2 + x"], "");
+    assert!(out.status.success());
+    assert_eq!(
+        stdout(&out).trim(),
+        concat!(
+            r#"[{"type":"Num","text":"2","start":0,"end":1},"#,
+            r#"{"type":"Plus","text":"+","start":2,"end":3},"#,
+            r#"{"type":"Name","text":"x","start":4,"end":5}]"#
+        )
+    );
+}
+
+#[test]
+fn json_tokens_reports_lexical_errors_on_stderr() {
+    let out = run(&["--json-tokens", "--eval", "2 @ 3"], "");
+    assert!(!out.status.success());
+    assert!(stdout(&out).is_empty());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unexpected character '@'"));
+}
+
+#[test]
+fn piped_statements_share_one_environment() {
+    let out = run(&[], "x = 2 + 3\nx * 2\n");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "5\n10\n");
+}
+
+#[test]
+fn piped_errors_go_to_stderr() {
+    let out = run(&[], "1 / 0\n");
+    assert!(!out.status.success());
+    assert!(stdout(&out).is_empty());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("DivisionByZero"));
+}
+
+#[test]
+fn silent_scripts_only_show_print() {
+    let dir = std::env::temp_dir().join(format!("tiny_calc_silent_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("script.calc");
+    std::fs::write(&script, "x = 2 + 3\nprint(x * 2)\nx\n").unwrap();
+    let out = run(&["--silent", script.to_str().unwrap()], "");
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "10\n");
+}
+
+#[test]
+fn json_output_for_a_success_and_an_error() {
+    let out = run(
+        &["--output=json", "--keep-going"],
+        "print(2) + print(3)\nx = 4\n1 / 0\n",
+    );
+    assert!(!out.status.success());
+    let stdout = stdout(&out);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines[..2],
+        [
+            r#"{"input":"print(2) + print(3)","result":5,"printed":[2,3]}"#,
+            r#"{"input":"x = 4","result":4}"#,
+        ]
+    );
+    assert!(
+        lines[2].starts_with(r#"{"input":"1 / 0","error":"#),
+        "{}",
+        lines[2]
+    );
+    assert_eq!(lines.len(), 3);
+}
+
+#[test]
+fn exact_mode_records_whole_answers() {
+    let out = run(&["--precision-exact"], "2 + 3\n1/2\nans(1) + 1\n");
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "5\n1/2\n6\n");
+}
@@ -0,0 +1,118 @@
+use rust_calc::{
+    Environment, MAX_HEIGHT, MAX_NESTING, Parser, eval_str, explain, parse, rational, rpn, sexpr,
+    tokenize, unparse,
+};
+
+/// Input that has to come back as `Ok` or `Err`, never as a panic or a
+/// stack overflow.
+fn corpus() -> Vec<String> {
+    let mut inputs: Vec<String> = [
+        "x",
+        "x + 1",
+        "2 * undefined_name",
+        "f(x)",
+        "2147483648",
+        "-2147483649",
+        "99999999999999999999",
+        "2147483647 + 1",
+        "-2147483648 - 1",
+        "-2147483648 * -1",
+        "-(-2147483648)",
+        "2 ^ 31",
+        "13!",
+        "1 / 0",
+        "1 % 0",
+        "-2147483648 / -1",
+        "x = 1 / 0",
+        "abs(-2147483648)",
+        "(",
+        ")",
+        "[1 + 2)",
+        "1 +",
+        "1 2",
+        ",",
+        "",
+    ]
+    .map(String::from)
+    .to_vec();
+    for depth in [MAX_NESTING, MAX_NESTING + 1, 10 * MAX_NESTING] {
+        inputs.push(format!("{}1{}", "(".repeat(depth), ")".repeat(depth)));
+        inputs.push(format!("{}1{}", "[".repeat(depth), "]".repeat(depth)));
+        inputs.push(format!("{}1", "-".repeat(depth)));
+        inputs.push(format!("{}1", "!".repeat(depth)));
+        inputs.push(format!("{}1", "2 ^ ".repeat(depth)));
+        inputs.push(format!("{}1{}", "abs(".repeat(depth), ")".repeat(depth)));
+        inputs.push(format!("1{}", "!".repeat(depth)));
+    }
+    for terms in [MAX_HEIGHT, MAX_HEIGHT + 1, 100 * MAX_HEIGHT] {
+        inputs.push(vec!["1"; terms].join(" + "));
+        inputs.push(vec!["1"; terms].join(" < "));
+        inputs.push(vec!["1"; terms].join(", "));
+    }
+    inputs.push(bracketed_chains(190));
+    inputs
+}
+
+/// Brackets nested `levels` deep, each holding a chain of its own. The
+/// parser only ever recurses `levels` deep, but the tree it would build
+/// is as tall as all the chains put together.
+fn bracketed_chains(levels: usize) -> String {
+    let mut source = "1".to_string();
+    for chain in 1..=levels {
+        source = format!("({source}{})", " + 1".repeat(chain));
+    }
+    source
+}
+
+#[test]
+fn nothing_in_the_corpus_panics() {
+    for input in corpus() {
+        let _ = eval_str(&input, &mut Environment::new());
+        let Ok(tokens) = tokenize(&input) else {
+            continue;
+        };
+        let Ok(expr) = parse(&mut Parser::new(tokens)) else {
+            continue;
+        };
+        let _ = unparse(&expr);
+        let _ = explain(&expr);
+        let _ = sexpr::to_sexpr(&expr);
+        let _ = rpn::to_postfix(&expr);
+        let _ = rational::evaluate_exact(&expr, &mut Environment::new());
+    }
+}
+
+#[test]
+fn a_long_flat_sum_is_not_nesting() {
+    let terms = MAX_NESTING + 1;
+    let sum = vec!["1"; terms].join(" + ");
+    assert_eq!(
+        eval_str(&sum, &mut Environment::new()).unwrap(),
+        terms as i32
+    );
+}
+
+#[test]
+fn an_over_tall_tree_is_refused_as_too_long() {
+    let sum = vec!["1"; MAX_HEIGHT + 1].join(" + ");
+    let message = eval_str(&sum, &mut Environment::new())
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("expression too long"), "{message}");
+    assert!(!message.contains("nested"), "{message}");
+
+    let message = eval_str(&bracketed_chains(190), &mut Environment::new())
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("levels tall"), "{message}");
+}
+
+#[test]
+fn deep_brackets_are_refused_as_nesting() {
+    let depth = MAX_NESTING + 1;
+    let source = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+    let message = eval_str(&source, &mut Environment::new())
+        .unwrap_err()
+        .to_string();
+    assert!(message.contains("nested more than"), "{message}");
+}